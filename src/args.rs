@@ -0,0 +1,202 @@
+use deep_duck::board::Board;
+use deep_duck::cache::{DEFAULT_CACHE_SIZE, MAX_CACHE_SIZE};
+use deep_duck::engine::{SearchLimits, SearchSettings, MAX_THREADS};
+use deep_duck::fen::FenError;
+use std::fmt;
+
+pub const DEFAULT_DEPTH: usize = 6;
+
+// Exit codes for scripts: the game was already over, or the arguments made no sense
+pub const EXIT_GAME_OVER: u8 = 1;
+pub const EXIT_USAGE: u8 = 2;
+
+pub const USAGE: &str =
+"Usage: deep-duck [options]
+
+Without options the engine starts its interactive prompt.
+
+    --fen <fen>         Starts from this position instead of the initial one
+    --depth <number>    Maximum depth to search (default 6)
+    --best              Prints the best movement, like \"e2e4,d5\", and exits
+    --eval              Prints the evaluation from the white point of view and exits
+    --protocol <name>   Talks with uci, xboard or repl (default repl)
+    --threads <number>  Threads searching together (default 1, at most 64)
+    --hash <megabytes>  Size of the cache of evaluated positions (default 16, at most 4096)
+    --help              Shows this help message
+
+Exits with 1 when the position is already over and 2 when the arguments are invalid.";
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Protocol {
+    Repl,
+    Uci,
+    XBoard,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ArgumentError {
+    Unknown(String),
+    MissingValue(&'static str),
+    InvalidValue(&'static str, String),
+    OutOfRange(&'static str, usize),
+    InvalidFen(FenError),
+    Conflict(&'static str, &'static str),
+}
+
+pub struct Arguments {
+    pub board: Board,
+    pub depth: usize,
+    pub threads: usize,
+    pub hash: usize,
+    pub protocol: Protocol,
+    pub best: bool,
+    pub eval: bool,
+    pub help: bool,
+}
+
+impl Default for Arguments {
+    fn default() -> Self {
+        Arguments {
+            board: Board::arranged(),
+            depth: DEFAULT_DEPTH,
+            threads: 1,
+            hash: DEFAULT_CACHE_SIZE,
+            protocol: Protocol::Repl,
+            best: false,
+            eval: false,
+            help: false,
+        }
+    }
+}
+
+impl Arguments {
+    // Values may come after a space or an equals sign, as in "--depth 6" or "--depth=6"
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, ArgumentError> {
+        let mut arguments = Arguments::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let (flag, inline) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+                _ => (arg, None),
+            };
+
+            let mut value = |name: &'static str| inline.clone().or_else(|| args.next()).ok_or(ArgumentError::MissingValue(name));
+
+            match flag.as_str() {
+                "--fen" => {
+                    let fen = value("--fen")?;
+                    arguments.board = Board::from_fen(&fen).map_err(ArgumentError::InvalidFen)?;
+                },
+                "--depth" => arguments.depth = positive("--depth", value("--depth")?)?,
+                "--threads" => arguments.threads = at_most("--threads", positive("--threads", value("--threads")?)?, MAX_THREADS)?,
+                "--hash" => arguments.hash = at_most("--hash", positive("--hash", value("--hash")?)?, MAX_CACHE_SIZE)?,
+                "--protocol" => {
+                    arguments.protocol = match value("--protocol")?.as_str() {
+                        "uci" => Protocol::Uci,
+                        "xboard" => Protocol::XBoard,
+                        "repl" => Protocol::Repl,
+                        other => return Err(ArgumentError::InvalidValue("--protocol", other.to_string())),
+                    }
+                },
+                "--best" => arguments.best = true,
+                "--eval" => arguments.eval = true,
+                "--help" | "-h" => arguments.help = true,
+                _ => return Err(ArgumentError::Unknown(flag)),
+            }
+        }
+
+        // the one shot mode does not talk with anyone
+        if arguments.one_shot() && arguments.protocol != Protocol::Repl {
+            let flag = if arguments.best { "--best" } else { "--eval" };
+            return Err(ArgumentError::Conflict(flag, "--protocol"));
+        }
+
+        Ok(arguments)
+    }
+
+    pub fn one_shot(&self) -> bool {
+        self.best || self.eval
+    }
+
+    pub fn limits(&self) -> SearchLimits {
+        SearchLimits::depth(self.depth)
+    }
+
+    pub fn settings(&self) -> SearchSettings {
+        SearchSettings {
+            threads: self.threads,
+            ..SearchSettings::default()
+        }
+    }
+}
+
+fn positive(flag: &'static str, value: String) -> Result<usize, ArgumentError> {
+    match value.parse::<usize>() {
+        Ok(number) if number > 0 => Ok(number),
+        _ => Err(ArgumentError::InvalidValue(flag, value)),
+    }
+}
+
+// Too many threads or megabytes would take the machine down instead of failing
+fn at_most(flag: &'static str, number: usize, max: usize) -> Result<usize, ArgumentError> {
+    if number > max {
+        Err(ArgumentError::OutOfRange(flag, max))
+    } else {
+        Ok(number)
+    }
+}
+
+impl fmt::Display for ArgumentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgumentError::Unknown(arg) => write!(f, "'{}' is not a valid argument", arg),
+            ArgumentError::MissingValue(flag) => write!(f, "{} needs a value", flag),
+            ArgumentError::InvalidValue(flag, value) => write!(f, "'{}' is not a valid value for {}", value, flag),
+            ArgumentError::OutOfRange(flag, max) => write!(f, "{} can be at most {}", flag, max),
+            ArgumentError::InvalidFen(error) => write!(f, "invalid FEN, {}", error),
+            ArgumentError::Conflict(first, second) => write!(f, "{} can not be used with {}", first, second),
+        }
+    }
+}
+
+impl std::error::Error for ArgumentError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Arguments, ArgumentError> {
+        Arguments::parse(args.iter().map(|x| x.to_string()))
+    }
+
+    #[test]
+    fn test_arguments() {
+        let arguments = parse(&["--fen", "4k3/8/8/8/8/8/8/4K3 b - - 0 1", "--depth=4", "--best", "--threads", "2"]).unwrap();
+        assert_eq!(arguments.board.to_fen(), "4k3/8/8/8/8/8/8/4K3 b - - 0 1");
+        assert_eq!(arguments.limits().depth, Some(4));
+        assert_eq!(arguments.settings().threads, 2);
+        assert!(arguments.one_shot());
+
+        let arguments = parse(&["--protocol", "uci", "--hash", "64"]).unwrap();
+        assert_eq!(arguments.protocol, Protocol::Uci);
+        assert_eq!(arguments.hash, 64);
+        assert!(!arguments.one_shot());
+
+        let arguments = parse(&[]).unwrap();
+        assert_eq!(arguments.protocol, Protocol::Repl);
+        assert_eq!(arguments.depth, DEFAULT_DEPTH);
+    }
+
+    #[test]
+    fn test_invalid_arguments() {
+        assert_eq!(parse(&["--deep"]).err(), Some(ArgumentError::Unknown("--deep".to_string())));
+        assert_eq!(parse(&["--depth"]).err(), Some(ArgumentError::MissingValue("--depth")));
+        assert_eq!(parse(&["--depth", "0"]).err(), Some(ArgumentError::InvalidValue("--depth", "0".to_string())));
+        assert_eq!(parse(&["--hash", "99999999999"]).err(), Some(ArgumentError::OutOfRange("--hash", MAX_CACHE_SIZE)));
+        assert_eq!(parse(&["--threads=65"]).err(), Some(ArgumentError::OutOfRange("--threads", MAX_THREADS)));
+        assert_eq!(parse(&["--protocol", "cecp"]).err(), Some(ArgumentError::InvalidValue("--protocol", "cecp".to_string())));
+        assert_eq!(parse(&["--eval", "--protocol", "uci"]).err(), Some(ArgumentError::Conflict("--eval", "--protocol")));
+        assert!(matches!(parse(&["--fen", "8/8 w"]).err(), Some(ArgumentError::InvalidFen(_))));
+    }
+}
@@ -5,52 +5,104 @@ use crate::pieces::Color;
 use crate::movements::Movement;
 use crate::pieces::PieceKind;
 use crate::fen;
+use crate::fen::FenError;
+use crate::zobrist;
 use std::iter::Flatten;
+use std::mem;
 use std::slice::Iter;
+use std::sync::Arc;
 
-#[derive(Clone)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Castle {
     pub short: bool,
     pub long: bool,
 }
 
+/// A duck chess position, with the history needed to find repetitions.
+/// Squares are given as [`Position`]s, from `Position(0, 0)` on a1 to `Position(7, 7)` on h8.
 #[derive(Clone)]
 pub struct Board {
     data: [Option<Piece>; 64],
-    pub duck: Option<Position>,
-    pub move_counter: usize,
-    pub active_color: Color,
+    // Every field is part of the Zobrist key, so outside the crate they are only read,
+    // new positions come from FEN or from playing movements
+    pub(crate) duck: Option<Position>,
+    pub(crate) halfmove_clock: usize,
+    pub(crate) fullmove_number: usize,
+    pub(crate) active_color: Color,
+    pub(crate) white_castle: Castle,
+    pub(crate) black_castle: Castle,
+    pub(crate) en_passant: Option<Position>,
+    key: u64,
+    // Only the positions since the last capture or pawn move can come back, the older
+    // ones are kept to take movements back and are shared by every copy of the board
+    history: Vec<u64>,
+    past: Option<Arc<Past>>,
+}
+
+struct Past {
+    history: Vec<u64>,
+    previous: Option<Arc<Past>>,
+}
+
+/// How a game ended, the color in the variants is the winner
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GameResult {
+    KingCaptured(Color),
+    NoMoves(Color),
+    FiftyMoves,
+    Repetition,
+}
+
+impl Castle {
+    pub fn none() -> Self {
+        Castle { short: false, long: false }
+    }
+}
+
+impl Default for Board {
+    fn default() -> Self {
+        Board::new()
+    }
 }
 
 impl Board {
+    /// An empty board, without even the kings
     pub fn new() -> Self {
         Board {
             data: [None; 64],
             duck: None,
-            move_counter: 0,
+            halfmove_clock: 0,
+            fullmove_number: 1,
             active_color: Color::White,
+            white_castle: Castle::none(),
+            black_castle: Castle::none(),
+            en_passant: None,
+            key: 0,
+            history: Vec::new(),
+            past: None,
         }
     }
 
-    pub fn from_fen(notation: &str) -> Self {
+    /// Reads a position in the Forsyth-Edwards notation, with the duck written as a `*`
+    pub fn from_fen(notation: &str) -> Result<Self, FenError> {
         fen::fen_to_board(notation)
     }
 
-    #[allow(dead_code)]
+    /// Writes the position in the Forsyth-Edwards notation
     pub fn to_fen(&self) -> String {
         fen::board_to_fen(self)
     }
     
-    #[allow(dead_code)]
+    /// The initial position, without the duck
     pub fn arranged() -> Self {
-        Board::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1")
+        Board::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap()
     }
 
     pub fn king_exists(&self, color: Color) -> bool {
         self.ocuppied_squares().any(|x| x.kind == PieceKind::King && x.color == color)
     }
 
-    pub fn ocuppied_squares(&self) -> Flatten<Iter<Option<Piece>>> {
+    pub fn ocuppied_squares(&self) -> Flatten<Iter<'_, Option<Piece>>> {
         self.data.iter().flatten()
     }
 
@@ -59,7 +111,7 @@ impl Board {
         self.data[index as usize]
     }
 
-    pub fn clear_square(&mut self, pos: Position) {
+    pub(crate) fn clear_square(&mut self, pos: Position) {
         if let Some(duck) = self.duck {
             if duck == pos {
                 self.duck = None;
@@ -68,64 +120,230 @@ impl Board {
 
         let index = pos.0 + 8 * pos.1;
         assert!(index >= 0);
+
+        if let Some(old) = self.data[index as usize] {
+            self.key ^= zobrist::piece_key(&old);
+        }
+
         self.data[index as usize] = None;
     }
     
-    pub fn set_square(&mut self, piece: Piece) {
+    pub(crate) fn set_square(&mut self, piece: Piece) {
         if let PieceKind::Duck = piece.kind {
             self.duck = Some(piece.pos);
         }
 
         let index = piece.pos.0 + 8 * piece.pos.1;
         assert!(index >= 0);
+
+        if let Some(old) = self.data[index as usize] {
+            self.key ^= zobrist::piece_key(&old);
+        }
+
+        self.key ^= zobrist::piece_key(&piece);
         self.data[index as usize] = Some(piece);
     }
 
-    pub fn drag_piece(&mut self, origin: Position, target: Position) {
+    pub(crate) fn drag_piece(&mut self, origin: Position, target: Position) {
         if let Some(mut square) = self.get_square(origin) {
             square.pos = target;
-            self.move_counter += 1;
             self.set_square(square);
             self.clear_square(origin);
         }
     }
 
+    pub fn duck(&self) -> Option<Position> {
+        self.duck
+    }
+
+    /// The player to move
+    pub fn active_color(&self) -> Color {
+        self.active_color
+    }
+
+    /// Plies since the last capture or pawn move, the game is drawn when it reaches 100
+    pub fn halfmove_clock(&self) -> usize {
+        self.halfmove_clock
+    }
+
+    /// Starts at 1 and grows after every black movement
+    pub fn fullmove_number(&self) -> usize {
+        self.fullmove_number
+    }
+
+    /// The square behind a pawn that just moved two squares, where it can be taken
+    pub fn en_passant(&self) -> Option<Position> {
+        self.en_passant
+    }
+
+    pub fn castle(&self, color: Color) -> Castle {
+        match color {
+            Color::White => self.white_castle,
+            Color::Black => self.black_castle,
+            Color::Yellow => Castle::none(),
+        }
+    }
+
+    /// The game ends as soon as a king is taken, or when the player to move is stuck.
+    /// In duck chess being stuck is a win, there is no such thing as a stalemate.
+    pub fn outcome(&self) -> Option<GameResult> {
+        if !self.king_exists(self.active_color) {
+            return Some(GameResult::KingCaptured(self.active_color.invert()));
+        }
+
+        if Movement::avaliable_moves(self).is_empty() {
+            return Some(GameResult::NoMoves(self.active_color));
+        }
+
+        self.draw()
+    }
+
+    pub fn draw(&self) -> Option<GameResult> {
+        if self.halfmove_clock >= 100 {
+            Some(GameResult::FiftyMoves)
+        } else if self.repetitions() >= 2 {
+            Some(GameResult::Repetition)
+        } else {
+            None
+        }
+    }
+
+    /// How many times the current position happened before.
+    /// Positions before a capture or pawn move can never come back.
+    pub fn repetitions(&self) -> usize {
+        // a position needs at least four plies to come back
+        if self.halfmove_clock < 4 {
+            return 0;
+        }
+
+        self.history
+            .iter()
+            .rev()
+            .take(self.halfmove_clock)
+            .filter(|&&x| x == self.key)
+            .count()
+    }
+
+    /// The 64 bits Zobrist hash of the position, kept up to date by every change on the board
+    pub fn zobrist_key(&self) -> u64 {
+        self.key
+    }
+
+    pub fn compute_key(&self) -> u64 {
+        let mut key = 0;
+
+        for piece in self.ocuppied_squares() {
+            key ^= zobrist::piece_key(piece);
+        }
+
+        if let Color::Black = self.active_color {
+            key ^= zobrist::color_key();
+        }
+
+        key ^= zobrist::castle_key(self.white_castle, self.black_castle);
+        key ^= zobrist::en_passant_key(self.en_passant);
+        key
+    }
+
+    // Needed after changing the fields by hand, like the FEN parser does
+    pub(crate) fn refresh_key(&mut self) {
+        self.key = self.compute_key();
+    }
+
+    /// Plays a movement, that must be valid in this position
     pub fn make_movement(&mut self, movement: Movement) {
+        self.history.push(self.key);
+
+        if movement.moved == PieceKind::Pawn || movement.captured.is_some() {
+            self.halfmove_clock = 0;
+            let history = mem::take(&mut self.history);
+            self.past = Some(Arc::new(Past { history, previous: self.past.take() }));
+        } else {
+            self.halfmove_clock += 1;
+        }
+
         self.drag_piece(movement.origin, movement.target);
+
+        // en passant is the only capture where the victim is not on the target square
+        if let Some(captured) = movement.captured {
+            if captured.pos != movement.target {
+                self.clear_square(captured.pos);
+            }
+        }
+
+        if let Some((rook_origin, rook_target)) = movement.castle_rook() {
+            self.clear_square(rook_origin);
+            self.set_square(Piece {pos:rook_target, color:movement.color, kind:PieceKind::Rook});
+        }
+
         self.place_duck(Some(movement.duck_target));
+        self.revoke_castle(movement.origin);
+        self.revoke_castle(movement.target);
+        self.set_en_passant(movement.en_passant_target());
         self.update_color();
 
+        if self.active_color == Color::White {
+            self.fullmove_number += 1;
+        }
+
         if let Some(kind) = movement.promotion {
             let mut piece = self.get_square(movement.target).unwrap();
             piece.kind = kind;
             self.set_square(piece);
         }
+
+        debug_assert_eq!(self.key, self.compute_key());
     }
 
-    #[allow(dead_code)]
+    /// Takes back the last movement played
     pub fn unmake_movement(&mut self, movement: Movement) {
         self.update_color();
+
+        if self.active_color == Color::Black {
+            self.fullmove_number -= 1;
+        }
+
         self.place_duck(movement.duck_origin);
         self.drag_piece(movement.target, movement.origin);
 
+        if let Some((rook_origin, rook_target)) = movement.castle_rook() {
+            self.clear_square(rook_target);
+            self.set_square(Piece {pos:rook_origin, color:movement.color, kind:PieceKind::Rook});
+        }
+
         if let Some(captured) = movement.captured {
             self.set_square(captured);
         }
 
+        self.set_castle(movement.castle_origin.0, movement.castle_origin.1);
+        self.set_en_passant(movement.en_passant_origin);
+        self.halfmove_clock = movement.halfmove_origin;
+
+        if self.history.is_empty() {
+            if let Some(past) = self.past.take() {
+                self.history = past.history.clone();
+                self.past = past.previous.clone();
+            }
+        }
+        self.history.pop();
+
         if movement.promotion.is_some() {
-            let mut piece = self.get_square(movement.target).unwrap();
+            let mut piece = self.get_square(movement.origin).unwrap();
             piece.kind = PieceKind::Pawn;
             self.set_square(piece);
         }
+
+        debug_assert_eq!(self.key, self.compute_key());
     }
 
+    /// A new board with the movement played, leaving this one as it is
     pub fn copy_movement(&self, movement: Movement) -> Self {
         let mut board = self.clone();
         board.make_movement(movement);
         board
     }
 
-    pub fn place_duck(&mut self, position: Option<Position>) {
+    pub(crate) fn place_duck(&mut self, position: Option<Position>) {
         if let Some(duck) = self.duck {
             self.clear_square(duck);
         }
@@ -136,11 +354,71 @@ impl Board {
         }
     }
 
+    // Moving the king or a rook, or having a rook captured, loses the right to castle with it
+    fn revoke_castle(&mut self, pos: Position) {
+        let (mut white, mut black) = (self.white_castle, self.black_castle);
+
+        match pos {
+            Position(4, 0) => white = Castle::none(),
+            Position(0, 0) => white.long = false,
+            Position(7, 0) => white.short = false,
+            Position(4, 7) => black = Castle::none(),
+            Position(0, 7) => black.long = false,
+            Position(7, 7) => black.short = false,
+            _ => return,
+        }
+
+        self.set_castle(white, black);
+    }
+
+    fn set_castle(&mut self, white: Castle, black: Castle) {
+        self.key ^= zobrist::castle_key(self.white_castle, self.black_castle);
+        self.key ^= zobrist::castle_key(white, black);
+        self.white_castle = white;
+        self.black_castle = black;
+    }
+
+    fn set_en_passant(&mut self, en_passant: Option<Position>) {
+        self.key ^= zobrist::en_passant_key(self.en_passant);
+        self.key ^= zobrist::en_passant_key(en_passant);
+        self.en_passant = en_passant;
+    }
+
     fn update_color(&mut self) {
+        self.key ^= zobrist::color_key();
         self.active_color = self.active_color.invert();
     }
 }
 
+impl GameResult {
+    pub fn winner(&self) -> Option<Color> {
+        match self {
+            GameResult::KingCaptured(color) | GameResult::NoMoves(color) => Some(*color),
+            GameResult::FiftyMoves | GameResult::Repetition => None,
+        }
+    }
+
+    /// The result as written in game records, like "1-0"
+    pub fn score(&self) -> &'static str {
+        match self.winner() {
+            Some(Color::White) => "1-0",
+            Some(Color::Black) => "0-1",
+            _ => "1/2-1/2",
+        }
+    }
+}
+
+impl fmt::Display for GameResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GameResult::KingCaptured(color) => write!(f, "{:?} wins by capturing the king", color),
+            GameResult::NoMoves(color) => write!(f, "{:?} wins with no movements left", color),
+            GameResult::FiftyMoves => write!(f, "Draw by the fifty move rule"),
+            GameResult::Repetition => write!(f, "Draw by repetition"),
+        }
+    }
+}
+
 impl fmt::Debug for Board {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut string = String::with_capacity(64);
@@ -165,4 +443,89 @@ impl fmt::Debug for Board {
 
         write!(f, "{}", &string)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zobrist_key() {
+        let mut board = Board::arranged();
+        let start = board.zobrist_key();
+        assert_eq!(start, board.compute_key());
+
+        let movement = Movement::try_movement(&board, Position(4, 1), Position(4, 3), Position(4, 2), None).unwrap();
+        board.make_movement(movement);
+        assert_ne!(board.zobrist_key(), start);
+        assert_eq!(board.zobrist_key(), Board::from_fen(&board.to_fen()).unwrap().zobrist_key());
+
+        board.unmake_movement(movement);
+        assert_eq!(board.zobrist_key(), start);
+    }
+
+    #[test]
+    fn test_outcome_no_moves() {
+        let board = Board::from_fen("7k/8/8/8/8/1p1p4/*P1P4/KRB5 w - - 0 1").unwrap();
+        assert_eq!(board.outcome(), Some(GameResult::NoMoves(Color::White)));
+
+        let board = Board::from_fen("7k/8/8/8/8/1p1p4/1P1P4/KRB2*2 w - - 0 1").unwrap();
+        assert_eq!(board.outcome(), None);
+    }
+
+    #[test]
+    fn test_outcome_king_captured() {
+        let mut board = Board::from_fen("k6Q/8/8/8/8/8/8/K7 w - - 0 1").unwrap();
+        let movement = Movement::try_movement(&board, Position(7, 7), Position(0, 7), Position(4, 4), None).unwrap();
+        board.make_movement(movement);
+        assert_eq!(board.outcome(), Some(GameResult::KingCaptured(Color::White)));
+    }
+
+    #[test]
+    fn test_outcome_repetition() {
+        let mut board = Board::arranged();
+        let shuffle = [
+            ("G1", "F3", "D5"), ("G8", "F6", "D4"),
+            ("F3", "G1", "D5"), ("F6", "G8", "D4"),
+        ];
+
+        for i in 0..9 {
+            assert_eq!(board.outcome(), None);
+            let (origin, target, duck) = shuffle[i % 4];
+            let movement = Movement::try_movement(
+                &board,
+                Position::from_str(origin).unwrap(),
+                Position::from_str(target).unwrap(),
+                Position::from_str(duck).unwrap(),
+                None,
+            ).unwrap();
+            board.make_movement(movement);
+        }
+
+        assert_eq!(board.outcome(), Some(GameResult::Repetition));
+    }
+
+    #[test]
+    fn test_history_after_pawn_move() {
+        let mut board = Board::arranged();
+        let knight = Movement::try_movement(&board, Position(6, 0), Position(5, 2), Position(3, 3), None).unwrap();
+        board.make_movement(knight);
+        assert_eq!(board.history.len(), 1);
+
+        let pawn = Movement::try_movement(&board, Position(4, 6), Position(4, 4), Position(3, 4), None).unwrap();
+        board.make_movement(pawn);
+        assert!(board.history.is_empty());
+
+        // taking the pawn move back brings the older positions back
+        board.unmake_movement(pawn);
+        assert_eq!(board.history.len(), 1);
+        board.unmake_movement(knight);
+        assert!(board.history.is_empty() && board.past.is_none());
+    }
+
+    #[test]
+    fn test_outcome_fifty_moves() {
+        let board = Board::from_fen("7k/8/8/8/8/8/8/K7 w - - 100 60").unwrap();
+        assert_eq!(board.outcome(), Some(GameResult::FiftyMoves));
+    }
+}
@@ -1,72 +1,168 @@
-
-use std::collections::HashMap;
+use std::mem::size_of;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU8, Ordering};
 
 use crate::board::Board;
-use crate::pieces::Piece;
-use crate::pieces::Color;
-use crate::engine::Evaluation;
-use crate::pieces::PieceKind;
+use crate::movements::Movement;
+
+pub const DEFAULT_CACHE_SIZE: usize = 16;
+
+/// Larger sizes are cut down to this one, in megabytes
+pub const MAX_CACHE_SIZE: usize = 4096;
+
+// Every shard has its own lock, so threads searching together rarely wait for each other
+const SHARDS: usize = 64;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Bound {
+    Exact,
+    Lower,
+    Upper,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Entry {
+    pub key: u64,
+    pub depth: usize,
+    pub score: i32,
+    pub bound: Bound,
+    pub movement: Option<Movement>,
+    pub age: u8,
+}
 
-const ZOBRIST_SIZE: usize = 64*7*3;
+// Every bucket keeps the deepest result it has seen, that is the most expensive to
+// recompute, and the most recent one, that is the most likely to be needed again.
+#[derive(Copy, Clone, Default)]
+struct Bucket {
+    deepest: Option<Entry>,
+    recent: Option<Entry>,
+}
 
+/// The positions already searched, shared by every thread and kept between searches
 pub struct ZobristCache {
-    random_values: [u32; ZOBRIST_SIZE],
-    color_value: u32,
-    data: HashMap<u32, Evaluation>,
+    shards: Vec<Mutex<Vec<Bucket>>>,
+    shard_len: usize,
+    age: AtomicU8,
+}
+
+impl Default for ZobristCache {
+    fn default() -> Self {
+        ZobristCache::new()
+    }
 }
 
 impl ZobristCache {
     pub fn new() -> Self {
-        
-        
+        ZobristCache::with_size(DEFAULT_CACHE_SIZE)
+    }
+
+    /// The size is given in megabytes, up to [`MAX_CACHE_SIZE`]
+    pub fn with_size(size: usize) -> Self {
+        let len = (size.min(MAX_CACHE_SIZE) * 1024 * 1024 / size_of::<Bucket>()).max(1);
+        let shards = len.min(SHARDS);
+        let shard_len = len / shards;
+
         ZobristCache {
-            random_values: [(); ZOBRIST_SIZE].map(|_| rand::random()),  // random values for every position
-            color_value: rand::random(),
-            data: HashMap::new(),
+            shards: (0..shards).map(|_| Mutex::new(vec![Bucket::default(); shard_len])).collect(),
+            shard_len,
+            age: AtomicU8::new(0),
         }
     }
 
-    pub fn insert(&mut self, board: &Board, eval: Evaluation) {
-        self.data.insert(self.zobrist_hash(board), eval);
+    pub fn size(&self) -> usize {
+        let megabyte = 1024 * 1024;
+        let len = self.shards.len() * self.shard_len;
+        (len * size_of::<Bucket>() + megabyte / 2) / megabyte
     }
 
-    pub fn get(&mut self, board: &Board) -> Option<Evaluation> {
-        self.data.get(&self.zobrist_hash(board)).copied()
+    pub fn clear(&self) {
+        for shard in self.shards.iter() {
+            shard.lock().unwrap().fill(Bucket::default());
+        }
+        self.age.store(0, Ordering::Relaxed);
     }
 
-    fn zobrist_hash(&self, board: &Board) -> u32 {
-        let mut hash: u32 = 0;
+    /// Entries from older searches are the first ones to be replaced
+    pub fn new_search(&self) {
+        self.age.fetch_add(1, Ordering::Relaxed);
+    }
 
-        for i in board.ocuppied_squares().map(|x| self.zobrist_value(*x)) {
-            hash ^= i;
-        }
+    pub fn insert(&self, board: &Board, depth: usize, score: i32, bound: Bound, movement: Option<Movement>) {
+        let key = board.zobrist_key();
+        let age = self.age.load(Ordering::Relaxed);
+        let entry = Entry { key, depth, score, bound, movement, age };
 
-        if let Color::White = board.active_color {
-            hash ^= self.color_value;
+        let (shard, index) = self.index(key);
+        let mut buckets = self.shards[shard].lock().unwrap();
+        let bucket = &mut buckets[index];
+
+        match bucket.deepest {
+            Some(old) if old.key == key => bucket.deepest = Some(entry),
+            Some(old) if old.age != age || depth >= old.depth => {
+                // the replaced entry is still the most recent of the others
+                bucket.recent = Some(old);
+                bucket.deepest = Some(entry);
+            },
+            Some(_) => bucket.recent = Some(entry),
+            None => bucket.deepest = Some(entry),
         }
+    }
 
-        hash
+    pub fn get(&self, board: &Board) -> Option<Entry> {
+        let key = board.zobrist_key();
+        let (shard, index) = self.index(key);
+        let bucket = self.shards[shard].lock().unwrap()[index];
+
+        [bucket.deepest, bucket.recent]
+            .into_iter()
+            .flatten()
+            .find(|x| x.key == key)
     }
 
-    fn zobrist_value(&self, piece: Piece) -> u32 {
-        let color_index = match piece.color {
-            Color::White => 0,
-            Color::Black => 1,
-            Color::Yellow => 2
-        };
-
-        let kind_index = match piece.kind {
-            PieceKind::Pawn => 0,
-            PieceKind::Rook => 1,
-            PieceKind::Knight => 2,
-            PieceKind::Bishop => 3,
-            PieceKind::Queen => 4,
-            PieceKind::King => 5,
-            PieceKind::Duck => 6,
-        };
-
-        let pos_index = piece.pos.0 + 8 * piece.pos.1;
-        let index = pos_index + color_index*64 + kind_index*64*2;
-        self.random_values[index as usize]
+    // The shard and the bucket inside of it
+    fn index(&self, key: u64) -> (usize, usize) {
+        let shards = self.shards.len() as u64;
+        let shard_len = self.shard_len as u64;
+        ((key % shards) as usize, (key / shards % shard_len) as usize)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replacement() {
+        // a single bucket, so every position fights for the same place
+        let cache = ZobristCache::with_size(0);
+        let first = Board::arranged();
+        let second = Board::from_fen("4k3/8/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        let third = Board::from_fen("4k3/8/8/8/8/8/8/4K3 b - - 0 1").unwrap();
+
+        cache.insert(&first, 5, 10, Bound::Exact, None);
+        cache.insert(&second, 2, 20, Bound::Lower, None);
+        assert_eq!(cache.get(&first).unwrap().depth, 5);
+        assert_eq!(cache.get(&second).unwrap().bound, Bound::Lower);
+
+        // the shallow entry is the one that goes away
+        cache.insert(&third, 1, 30, Bound::Upper, None);
+        assert!(cache.get(&first).is_some());
+        assert!(cache.get(&second).is_none());
+
+        // in a new search old entries may be replaced, even if they are deeper,
+        // and the replaced one takes the place of the most recent
+        cache.new_search();
+        cache.insert(&second, 1, 20, Bound::Exact, None);
+        assert_eq!(cache.get(&second).unwrap().bound, Bound::Exact);
+        assert_eq!(cache.get(&first).unwrap().depth, 5);
+        assert!(cache.get(&third).is_none());
+
+        // a deeper entry also keeps the one it replaces
+        cache.insert(&third, 3, 30, Bound::Exact, None);
+        assert!(cache.get(&second).is_some());
+        assert!(cache.get(&first).is_none());
+
+        cache.clear();
+        assert!(cache.get(&second).is_none());
+    }
+}
@@ -1,13 +1,16 @@
-use crate::board::Board;
-use crate::pieces::Position;
-use crate::pieces::Color;
-use crate::movements::Movement;
-use crate::cache::ZobristCache;
-use crate::engine::{search_cached, evaluate_cached};
-use crate::evaluation::piece_value;
-use crate::pieces::PieceKind;
+use deep_duck::board::Board;
+use deep_duck::pieces::Position;
+use deep_duck::pieces::Color;
+use deep_duck::movements::Movement;
+use deep_duck::cache::ZobristCache;
+use deep_duck::engine::{evaluate_limited, mate_in, BackgroundSearch, DuckSearch, Evaluation, SearchLimits, SearchSettings, MAX_THREADS};
+use deep_duck::pieces::PieceKind;
+use crate::args::{Arguments, EXIT_GAME_OVER};
 use colored::Colorize;
-use std::time::Instant;
+use std::sync::Arc;
+use std::sync::mpsc::Sender;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
 
 #[derive(Debug)]
 pub enum Command {
@@ -19,18 +22,58 @@ pub enum Command {
     Play,
     Fen(String),
     Depth(usize),
+    Time(Option<Duration>),
+    Nodes(Option<u64>),
+    Ducks(DuckSearch),
+    Underpromotions(bool),
+    Reductions(bool),
+    Futility(bool),
+    Hash(usize),
+    Threads(usize),
+    ClearHash,
     Move(String),
     Analyze(String),
+    Infinite,
+    Stop,
+    Uci,
+    XBoard,
     Exit,
     Clear,
     Empty,
     Invalid,
+    InvalidValue(&'static str, String),
+    OutOfRange(&'static str, usize),
+}
+
+// The main loop waits for whatever comes first: a line typed by the user or the end of a search
+pub enum Event {
+    Input(String),
+    EndOfInput,
+    SearchDone(Evaluation),
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum SearchKind {
+    Evaluate,
+    Sugest,
+    Play,
+    Analyze,
+}
+
+// A search running in the background, the result comes back as an event
+struct Search {
+    kind: SearchKind,
+    background: BackgroundSearch,
+    start: Instant,
 }
 
 pub struct App {
     board: Board,
-    cache: ZobristCache,
-    depth: usize,
+    cache: Arc<ZobristCache>,
+    settings: SearchSettings,
+    limits: SearchLimits,
+    events: Sender<Event>,
+    search: Option<Search>,
 }
 
 const HELP_MESSAGE: &str = 
@@ -49,43 +92,152 @@ These are the avaliable commands:
     rearange        Rearange the board to the initial position
     fen [notation]  Loads loads the board acording to the given FEN notation 
     depth [number]  Sets the maximum depth to evaluate
+    time [seconds]  Stops thinking after some time, 0 to think until the maximum depth
+    nodes [number]  Stops thinking after some positions, 0 to think until the maximum depth
+    ducks [mode]    Tries only the heuristic duck squares or every square (heuristic/full)
+    underpromotions [on/off]  Lets the engine consider promoting to rooks and bishops
+    reductions [on/off]  Searches late quiet movements with less depth
+    futility [on/off]    Stops searching hopeless positions near the leaves
+    hash [megabytes]  Resizes the cache of evaluated positions
+    hash clear      Forgets every evaluated position
+    threads [number]  Searches with many threads at once, sharing the cache
 
     evaluate        Evaluates the position and shows a pontuation
     sugest          The computer sugests the best movement
     play            The computer plays the best movement in the current board
+    think           Thinks until stopped, showing the best line of every depth
+    move [move]     Plays a movement, like \"move Nf3@d5\" or \"move g1 f3 d5\"
+    analyze [move]  Tells how good a movement is, like \"analyze e4@d5\"
+    stop            Stops thinking and uses the best movement found so far
+
+    uci             Speaks the UCI protocol from now on, for chess GUIs and match runners
+    xboard          Speaks the XBoard protocol from now on, for WinBoard and friends
 ";
 
 impl App {
-    pub fn new() -> Self {
+    pub fn new(events: Sender<Event>, board: Board, limits: SearchLimits, settings: SearchSettings, cache: Arc<ZobristCache>) -> Self {
         App {
-            board: Board::arranged(),
-            cache: ZobristCache::new(),
-            depth: 6,
+            board,
+            cache,
+            settings,
+            limits,
+            events,
+            search: None,
         }
     }
 
+    pub fn is_searching(&self) -> bool {
+        self.search.is_some()
+    }
+
     pub fn run(&mut self, command: Command) {
+        let passive = matches!(command, Command::Help | Command::Board | Command::Clear | Command::Stop | Command::Invalid | Command::InvalidValue(..) | Command::OutOfRange(..));
+
+        if self.is_searching() && !passive {
+            println!("The engine is thinking, type stop first.");
+            return;
+        }
+
         let start = Instant::now();
         match command {
+            Command::Evaluate => return self.start_search(SearchKind::Evaluate),
+            Command::Sugest => return self.start_search(SearchKind::Sugest),
+            Command::Play => return self.start_search(SearchKind::Play),
+            Command::Infinite => return self.start_search(SearchKind::Analyze),
+            Command::Stop => return self.stop_search(),
             Command::Help => self.print_help(),
             Command::Board => self.print_board(),
             Command::Rearange => self.rearange(),
-            Command::Evaluate => self.show_evaluation(),
-            Command::Sugest => self.sugest_movement(),
-            Command::Play => self.computer_move(),
             Command::Fen(fen) => self.load_board(&fen),
             Command::Move(coords) => self.try_movement(&coords),
             Command::Analyze(coords) => self.analyze_movement(&coords),
             Command::Depth(depth) => self.change_depth(depth),
+            Command::Time(time) => self.limits.time = time,
+            Command::Nodes(nodes) => self.limits.nodes = nodes,
+            Command::Ducks(mode) => self.change_ducks(mode),
+            Command::Underpromotions(enabled) => self.change_underpromotions(enabled),
+            Command::Reductions(enabled) => self.settings.reductions = enabled,
+            Command::Futility(enabled) => self.change_futility(enabled),
+            Command::Hash(size) => self.resize_cache(size),
+            Command::Threads(threads) => self.settings.threads = threads,
+            Command::ClearHash => self.cache.clear(),
             Command::Clear => App::clear_terminal(),
             Command::Invalid => App::invalid(),
-            Command::Exit | Command::Empty => (),
+            Command::InvalidValue(command, value) => println!("'{}' is not a valid value for {}.", value, command),
+            Command::OutOfRange(command, max) => println!("{} can be at most {}.", command, max),
+            Command::Uci | Command::XBoard | Command::Exit | Command::Empty => (),
         }
         let duration = start.elapsed();
         println!("Time elapsed: {:?}", duration);
     }
 
-    fn decode_positions(coords: &str) -> Option<(Position, Position, Position)> {
+    fn start_search(&mut self, kind: SearchKind) {
+        if self.game_over() {
+            return;
+        }
+
+        let limits = match kind {
+            SearchKind::Analyze => SearchLimits::default(),
+            _ => self.limits,
+        };
+
+        let board = self.board.clone();
+        let events = self.events.clone();
+
+        let report = move |depth, evaluation: &Evaluation| {
+            if kind == SearchKind::Analyze {
+                App::print_iteration(depth, evaluation, &board);
+            }
+        };
+
+        let background = BackgroundSearch::start(
+            self.board.clone(),
+            limits,
+            Arc::clone(&self.cache),
+            self.settings,
+            report,
+            move |evaluation| { let _ = events.send(Event::SearchDone(evaluation)); },
+        );
+
+        if kind == SearchKind::Analyze {
+            println!("Thinking until you type stop.");
+        }
+
+        self.search = Some(Search { kind, background, start: Instant::now() });
+    }
+
+    fn stop_search(&mut self) {
+        match &self.search {
+            Some(search) => search.background.stop(),
+            None => println!("The engine is not thinking."),
+        }
+    }
+
+    // Searches with limits are allowed to finish, but an analysis would never end
+    pub fn close(&mut self) {
+        if let Some(search) = &self.search {
+            if search.kind == SearchKind::Analyze {
+                search.background.stop();
+            }
+        }
+    }
+
+    pub fn finish_search(&mut self, evaluation: Evaluation) {
+        let search = match self.search.take() {
+            Some(search) => search,
+            None => return,
+        };
+
+        match search.kind {
+            SearchKind::Evaluate => self.show_evaluation(&evaluation),
+            SearchKind::Sugest | SearchKind::Analyze => self.sugest_movement(&evaluation),
+            SearchKind::Play => self.computer_move(&evaluation),
+        }
+
+        println!("Time elapsed: {:?}", search.start.elapsed());
+    }
+
+    fn decode_positions(coords: &str) -> Option<(Position, Position, Position, Option<PieceKind>)> {
         let mut splited = coords.split(' ');
 
         let origin = match splited.next() {
@@ -103,59 +255,72 @@ impl App {
             None => None
         };
 
+        let promotion = match splited.next() {
+            None => None,
+            Some("q") | Some("Q") => Some(PieceKind::Queen),
+            Some("r") | Some("R") => Some(PieceKind::Rook),
+            Some("b") | Some("B") => Some(PieceKind::Bishop),
+            Some("n") | Some("N") => Some(PieceKind::Knight),
+            Some(_) => return None,
+        };
+
         if let (Some(origin), Some(target), Some(duck)) = (origin, target, duck) {
-            Some((origin, target, duck))
+            Some((origin, target, duck, promotion))
         } else {
             None
         }
     }
 
-    fn try_movement(&mut self, coords: &str) {
-        let decoded = App::decode_positions(coords);
-
-        if decoded.is_none() {
-            App::invalid();
-            return;
+    // Movements are written in algebraic notation, like "Nf3@d5", or as raw squares, like "g1 f3 d5"
+    fn read_movement(&self, input: &str) -> Option<Movement> {
+        if let Some((origin, target, duck, promotion)) = App::decode_positions(input) {
+            let movement = Movement::try_movement(&self.board, origin, target, duck, promotion);
+            if movement.is_none() {
+                App::invalid_movement();
+            }
+            return movement;
         }
 
-        let (origin, target, duck) = decoded.unwrap();
-
-        if let Some(movement) = Movement::try_movement(&self.board, origin, target, duck) {
-            self.board.make_movement(movement);
-            println!("{:?}", self.board);    
-            println!("You moved: {:?} to {:?} and duck to {:?}", movement.origin, movement.target, movement.duck_target);
-        } else {
-            App::invalid_movement();
+        match Movement::from_san(&self.board, input) {
+            Ok(movement) => Some(movement),
+            Err(error) => {
+                println!("Invalid movement: {}.", error);
+                None
+            },
         }
     }
 
-    fn analyze_movement(&mut self, coords: &str) {
-        let decoded = App::decode_positions(coords);
-
-        if decoded.is_none() {
-            App::invalid();
+    fn try_movement(&mut self, input: &str) {
+        if self.game_over() {
             return;
         }
 
-        let (origin, target, duck) = decoded.unwrap();
+        if let Some(movement) = self.read_movement(input) {
+            let san = movement.to_san(&self.board);
+            self.board.make_movement(movement);
+            println!("{:?}", self.board);    
+            println!("You moved: {}", san);
+            self.game_over();
+        }
+    }
 
-        if let Some(movement) = Movement::try_movement(&self.board, origin, target, duck) {
+    fn analyze_movement(&mut self, input: &str) {
+        if let Some(movement) = self.read_movement(input) {
             let tmp_board = self.board.copy_movement(movement);
-            let done = -evaluate_cached(&tmp_board, self.depth-1, &mut self.cache).score;
-            let expected = evaluate_cached(&self.board, self.depth, &mut self.cache).score;
+            let depth = self.limits.depth.unwrap_or(1).max(1);
+            let reply_limits = SearchLimits { depth: Some(depth - 1), ..self.limits };
+            let done = -evaluate_limited(&tmp_board, reply_limits, &self.cache, &self.settings).score;
+            let expected = evaluate_limited(&self.board, self.limits, &self.cache, &self.settings).score;
             App::compare_scores(done, expected)
-        } else {
-            App::invalid_movement();
         }
     }
 
     fn compare_scores(done: i32, expected: i32) {
         let diff = expected - done;
 
-        if (expected >= piece_value(PieceKind::King))
-            && (done < piece_value(PieceKind::King)
-            && (done > 250))
-        {
+        let winning = |score| mate_in(score).is_some_and(|x| x > 0);
+
+        if winning(expected) && !winning(done) && done > 250 {
             println!("{} Missed Win", ":(".yellow().bold());
             return;
         }
@@ -168,6 +333,15 @@ impl App {
         }
     }
 
+    fn game_over(&self) -> bool {
+        if let Some(result) = self.board.outcome() {
+            println!("{} {} ({})", "Game over:".bold(), result, result.score());
+            true
+        } else {
+            false
+        }
+    }
+
     fn invalid_movement() {
         println!("This is not a valid duck chess movement.")
     }
@@ -184,70 +358,127 @@ impl App {
         println!("{:?}", self.board)
     }
 
-    fn show_evaluation(&mut self) {
-        let evaluation = evaluate_cached(&self.board, self.depth, &mut self.cache);
-
-        let score = match self.board.active_color {
+    fn show_evaluation(&self, evaluation: &Evaluation) {
+        let score = match self.board.active_color() {
             Color::White => evaluation.score,
             Color::Black => -evaluation.score,
             _ => 0,
         };
 
+        // positive when white captures the king, negative when black does
+        let mate = mate_in(score);
+
         // this is cringe but very convenient =)
         let bar = match score {
-            ..= -1_000_000         => "○○○○○○○○○○○○○○○○○○○○",
-            (-999_999 ..= -1_000)  => "●○○○○○○○○○○○○○○○○○○○",
+            _ if mate.is_some_and(|x| x < 0) => "○○○○○○○○○○○○○○○○○○○○",
+            _ if mate.is_some()              => "●●●●●●●●●●●●●●●●●●●●",
+            ..= -1_000             => "●○○○○○○○○○○○○○○○○○○○",
             (-999  ..= -300)       => "●●●●●○○○○○○○○○○○○○○○",
             (-299 ..= -100)        => "●●●●●●●●○○○○○○○○○○○○",
             (-99 ..= 99)           => "●●●●●●●●●●○○○○○○○○○○",
             (100  ..= 299)         => "●●●●●●●●●●●●○○○○○○○○",
             (300  ..= 999)         => "●●●●●●●●●●●●●●●●○○○○",
-            (1_000  ..= 999_999)   => "●●●●●●●●●●●●●●●●●●●○",
-            1_000_000 ..           => "●●●●●●●●●●●●●●●●●●●●",
+            1_000 ..               => "●●●●●●●●●●●●●●●●●●●○",
         };
 
-        if score >= 1_000_000 {
-            println!("White has mate in {}", evaluation.depth)
-        }
-        else if score <= -1_000_000 {
-            println!("Black has mate in {}", evaluation.depth)
+        if let Some(moves) = mate {
+            println!("Mate: #{}", moves)
         }
         else {
             println!("Points: {}", score/100)
         }
         println!("{}", bar);
+        App::print_pv(&self.board, &evaluation.pv);
+        App::print_stats(evaluation);
     }
 
-    fn sugest_movement(&mut self) {
-        let best_move = search_cached(&self.board, self.depth, &mut self.cache);
-        if let Some(movement) = best_move {
-            println!("Move: {:?} to {:?} and duck to {:?}", movement.origin, movement.target, movement.duck_target);
+    fn sugest_movement(&self, evaluation: &Evaluation) {
+        if let Some(movement) = evaluation.movement {
+            println!("Move: {}", movement.to_san(&self.board));
+            App::print_pv(&self.board, &evaluation.pv);
+            App::print_stats(evaluation);
         } else {
             println!("There are no movements for your position.");
         }
     }
 
-    fn computer_move(&mut self) {
-        let best_move = search_cached(&self.board, self.depth, &mut self.cache);
-        if let Some(movement) = best_move {
+    fn print_pv(board: &Board, pv: &[Movement]) {
+        if pv.is_empty() {
+            return;
+        }
+
+        println!("Line: {}", san_line(board, pv));
+    }
+
+    // One line for every depth of an analysis, scores are from the white point of view
+    fn print_iteration(depth: usize, evaluation: &Evaluation, board: &Board) {
+        let score = white_score(evaluation.score, board.active_color());
+        let stats = &evaluation.stats;
+        println!("depth {} seldepth {} score {} nodes {} nps {} line {}",
+            depth, stats.max_ply, score, stats.total_nodes(), stats.nodes_per_second(), san_line(board, &evaluation.pv));
+    }
+
+    fn print_stats(evaluation: &Evaluation) {
+        let stats = &evaluation.stats;
+        println!("Depth: {} (selective {})", evaluation.depth, stats.max_ply);
+        println!("Nodes: {} ({} in quiescence), {} per second", stats.total_nodes(), stats.qnodes, stats.nodes_per_second());
+        println!("Cutoffs: {} ({:.1}% on the first movement), {} on ducks", stats.cutoffs, 100.0 * stats.first_cutoff_rate(), stats.duck_cutoffs);
+        println!("Cache: {} probes ({:.1}% hits)", stats.cache_probes, 100.0 * stats.cache_hit_rate());
+        println!("Re-searches: {} ({} at the root)", stats.researches, stats.aspiration_researches);
+    }
+
+    fn computer_move(&mut self, evaluation: &Evaluation) {
+        if let Some(movement) = evaluation.movement {
+            let san = movement.to_san(&self.board);
             self.board.make_movement(movement);
             println!("{:?}", self.board);
-            println!("Computer moved: {:?} to {:?} and duck to {:?}", movement.origin, movement.target, movement.duck_target);
+            println!("Computer moved: {}", san);
+            self.game_over();
         } else {
             println!("There are no movements for this position.");
         }
     }
 
     fn load_board(&mut self, fen: &str) {
-        self.board = Board::from_fen(fen);
-        println!("{:?}", self.board);
+        match Board::from_fen(fen) {
+            Ok(board) => {
+                self.board = board;
+                println!("{:?}", self.board);
+            },
+            Err(error) => println!("Invalid FEN: {}.", error),
+        }
     }
 
     fn change_depth(&mut self, depth: usize) {
-        if depth > 6 {
-            println!("Be carefull, this may take an eternity to run.")
+        if depth > 6 && self.limits.time.is_none() && self.limits.nodes.is_none() {
+            println!("Be carefull, this may take an eternity to run. Consider setting a time limit.")
         }
-        self.depth = depth
+        self.limits.depth = Some(depth)
+    }
+
+    fn change_ducks(&mut self, mode: DuckSearch) {
+        if let DuckSearch::Full = mode {
+            println!("Trying every duck square is a lot slower, consider a smaller depth.")
+        }
+        // evaluations from the other mode would mix up the comparison
+        self.cache.clear();
+        self.settings.ducks = mode;
+    }
+
+    fn change_underpromotions(&mut self, enabled: bool) {
+        // the cached best movements may not be searched anymore, or miss new ones
+        self.cache.clear();
+        self.settings.underpromotions = enabled;
+    }
+
+    fn change_futility(&mut self, enabled: bool) {
+        self.settings.futility = enabled;
+        self.settings.reverse_futility = enabled;
+    }
+
+    fn resize_cache(&mut self, size: usize) {
+        self.cache = Arc::new(ZobristCache::with_size(size));
+        println!("Cache size: {} MB", self.cache.size());
     }
 
     fn clear_terminal() {
@@ -259,6 +490,59 @@ impl App {
     }
 }
 
+// Shown as "e4@d5 e5@e4 ...", every movement written from the position it was played in
+fn san_line(board: &Board, pv: &[Movement]) -> String {
+    let mut board = board.clone();
+    let mut line = Vec::with_capacity(pv.len());
+
+    for movement in pv {
+        line.push(movement.to_san(&board));
+        board.make_movement(*movement);
+    }
+
+    line.join(" ")
+}
+
+// Like "+0.35" or "#-2", always from the white point of view
+fn white_score(score: i32, color: Color) -> String {
+    let score = match color {
+        Color::Black => -score,
+        _ => score,
+    };
+
+    match mate_in(score) {
+        Some(moves) => format!("#{}", moves),
+        None => format!("{:+.2}", score as f64 / 100.0),
+    }
+}
+
+// Searches the position given in the arguments and prints only what was asked for,
+// so scripts can read the answer without talking with the prompt
+pub fn run_once(arguments: &Arguments) -> ExitCode {
+    let board = &arguments.board;
+
+    if let Some(result) = board.outcome() {
+        eprintln!("Game over: {} ({})", result, result.score());
+        return ExitCode::from(EXIT_GAME_OVER);
+    }
+
+    let cache = ZobristCache::with_size(arguments.hash);
+    let evaluation = evaluate_limited(board, arguments.limits(), &cache, &arguments.settings());
+
+    if arguments.eval {
+        println!("{}", white_score(evaluation.score, board.active_color()));
+    }
+
+    if arguments.best {
+        match evaluation.movement {
+            Some(movement) => println!("{}", movement),
+            None => println!("0000"),
+        }
+    }
+
+    ExitCode::SUCCESS
+}
+
 impl Command {
     pub fn from_str(input: String) -> Self {
         let (key, val) = match input.trim().split_once(' ') {
@@ -278,15 +562,91 @@ impl Command {
             "play" => Command::Play,
             "fen" => Command::Fen(val.to_string()),
             "move" => Command::Move(val.to_string()),
+            "think" => Command::Infinite,
             "analyze" => Command::Analyze(val.to_string()),
+            "stop" => Command::Stop,
+            "uci" => Command::Uci,
+            "xboard" => Command::XBoard,
             "depth" => {
-                if let Ok(number) = val.parse::<usize>() {
-                    Command::Depth(number)
+                match val.parse::<usize>() {
+                    Ok(number) if number > 0 => Command::Depth(number),
+                    _ => Command::InvalidValue("depth", val.to_string()),
+                }
+            },
+            "ducks" => match val {
+                "heuristic" => Command::Ducks(DuckSearch::Heuristic),
+                "full" => Command::Ducks(DuckSearch::Full),
+                _ => Command::Invalid,
+            },
+            "time" => {
+                match val.parse::<f64>() {
+                    Ok(seconds) if seconds > 0.0 => Command::Time(Some(Duration::from_secs_f64(seconds))),
+                    Ok(_) => Command::Time(None),
+                    Err(_) => Command::Invalid,
+                }
+            },
+            "nodes" => {
+                match val.parse::<u64>() {
+                    Ok(0) => Command::Nodes(None),
+                    Ok(number) => Command::Nodes(Some(number)),
+                    Err(_) => Command::Invalid,
+                }
+            },
+            "threads" => {
+                match val.parse::<usize>() {
+                    Ok(number) if number > MAX_THREADS => Command::OutOfRange("threads", MAX_THREADS),
+                    Ok(number) if number > 0 => Command::Threads(number),
+                    _ => Command::InvalidValue("threads", val.to_string()),
+                }
+            },
+            "hash" => {
+                if val == "clear" {
+                    Command::ClearHash
+                } else if let Ok(number) = val.parse::<usize>() {
+                    Command::Hash(number)
                 } else {
                     Command::Invalid
                 }
             },
+            "underpromotions" => match val {
+                "on" => Command::Underpromotions(true),
+                "off" => Command::Underpromotions(false),
+                _ => Command::Invalid,
+            },
+            "reductions" => match val {
+                "on" => Command::Reductions(true),
+                "off" => Command::Reductions(false),
+                _ => Command::Invalid,
+            },
+            "futility" => match val {
+                "on" => Command::Futility(true),
+                "off" => Command::Futility(false),
+                _ => Command::Invalid,
+            },
             _ => Command::Invalid,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> Command {
+        Command::from_str(input.to_string())
+    }
+
+    #[test]
+    fn test_depth_command() {
+        assert!(matches!(parse("depth 4"), Command::Depth(4)));
+        assert!(matches!(parse("depth 0"), Command::InvalidValue("depth", value) if value == "0"));
+        assert!(matches!(parse("depth four"), Command::InvalidValue("depth", _)));
+    }
+
+    #[test]
+    fn test_threads_command() {
+        assert!(matches!(parse("threads 4"), Command::Threads(4)));
+        assert!(matches!(parse("threads 100000"), Command::OutOfRange("threads", MAX_THREADS)));
+        assert!(matches!(parse("threads 0"), Command::InvalidValue("threads", _)));
+    }
+}
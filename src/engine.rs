@@ -1,10 +1,15 @@
 use std::cmp::Ordering;
+use std::sync::Arc;
+use std::sync::atomic::{self, AtomicBool};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 use crate::board::Board;
 use crate::pieces::PieceKind;
 use crate::pieces::Position;
 use crate::movements::Movement;
-use crate::cache::ZobristCache;
+use crate::cache::{Bound, ZobristCache};
+use crate::ordering::MoveOrdering;
 use crate::evaluation::{count_centipawns, piece_value};
 
 
@@ -14,13 +19,235 @@ struct Prune {
     beta: i32,
 }
 
-#[derive(Copy, Clone, Debug)]
+/// The principal variation is the line both sides are expected to play,
+/// starting with the movement itself
+#[derive(Clone, Debug, Default)]
 pub struct Evaluation {
     pub movement: Option<Movement>,
     pub score: i32,
     pub depth: usize,
+    pub pv: Vec<Movement>,
+    /// only filled in for the root of the search
+    pub stats: SearchStats,
+}
+
+/// How the search went, counted by every thread together
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SearchStats {
+    pub nodes: u64,
+    pub qnodes: u64,
+    /// beta cutoffs, and how many of them happened with the first movement tried
+    pub cutoffs: u64,
+    pub first_cutoffs: u64,
+    /// ducks that were not tried because the enemy reply was already too good
+    pub duck_cutoffs: u64,
+    pub cache_probes: u64,
+    pub cache_hits: u64,
+    /// the deepest position reached, quiescence included
+    pub max_ply: usize,
+    /// movements searched again after a null window, and root searches after an aspiration window
+    pub researches: u64,
+    pub aspiration_researches: u64,
+    pub elapsed: Duration,
+}
+
+/// The duck squares tried for every movement: a few chosen by heuristics, or all of them
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DuckSearch {
+    Heuristic,
+    Full,
+}
+
+/// How the search works, the defaults are the strongest settings
+#[derive(Copy, Clone, Debug)]
+pub struct SearchSettings {
+    pub ducks: DuckSearch,
+    pub underpromotions: bool,
+    /// killer movements and history tables
+    pub ordering: bool,
+    /// null windows for every movement but the first one
+    pub pvs: bool,
+    /// a narrow window around the last iteration score at the root
+    pub aspiration: bool,
+    /// threads searching together, sharing the cache
+    pub threads: usize,
+    /// late quiet movements are searched with less depth, unless they look good
+    pub reductions: bool,
+    pub late_moves: usize,
+    /// near the leaves, positions far away from the window are not searched further
+    pub futility: bool,
+    pub reverse_futility: bool,
+    pub futility_margin: i32,
+}
+
+/// Any combination of limits can be used, the search stops at the first one reached
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SearchLimits {
+    pub depth: Option<usize>,
+    pub time: Option<Duration>,
+    pub nodes: Option<u64>,
 }
 
+struct SearchContext<'a> {
+    cache: &'a ZobristCache,
+    settings: &'a SearchSettings,
+    // raised by another thread when it is time to stop
+    stop: &'a AtomicBool,
+    limits: SearchLimits,
+    ordering: MoveOrdering,
+    start: Instant,
+    stats: SearchStats,
+    completed: usize,
+    stopped: bool,
+    // only the main thread has to finish an iteration before stopping
+    helper: bool,
+}
+
+const MAX_DEPTH: usize = 64;
+
+/// More threads than this only get in the way of each other
+pub const MAX_THREADS: usize = 64;
+
+/// Capturing the king is worth MATE minus the plies from the root until it happens,
+/// so a faster win is always preferred. Anything above MATE_BOUND is one of these.
+pub const MATE: i32 = 1_000_000;
+const MATE_BOUND: i32 = MATE - 1_000;
+const DELTA_MARGIN: i32 = 200;
+const CAPTURE_ORDER: i32 = 100_000_000;
+const KILLER_ORDER: i32 = 1_000_000;
+const ASPIRATION_WINDOW: i32 = 50;
+const ASPIRATION_LIMIT: i32 = 500;
+const FUTILITY_DEPTH: usize = 2;
+
+// The GUI needs some time to receive the movement, so the clock is never used until the end
+const MOVE_OVERHEAD: Duration = Duration::from_millis(50);
+/// Without a time control, the remaining time is shared by this many movements
+pub const MOVES_TO_GO: u32 = 30;
+
+impl Default for SearchSettings {
+    fn default() -> Self {
+        SearchSettings {
+            ducks: DuckSearch::Heuristic,
+            underpromotions: false,
+            ordering: true,
+            pvs: true,
+            aspiration: true,
+            threads: 1,
+            reductions: true,
+            late_moves: 3,
+            futility: true,
+            reverse_futility: true,
+            futility_margin: 200,
+        }
+    }
+}
+
+impl SearchStats {
+    pub fn total_nodes(&self) -> u64 {
+        self.nodes + self.qnodes
+    }
+
+    pub fn nodes_per_second(&self) -> u64 {
+        let seconds = self.elapsed.as_secs_f64();
+
+        if seconds > 0.0 {
+            (self.total_nodes() as f64 / seconds) as u64
+        } else {
+            0
+        }
+    }
+
+    /// In a well ordered search most cutoffs come from the first movement
+    pub fn first_cutoff_rate(&self) -> f64 {
+        ratio(self.first_cutoffs, self.cutoffs)
+    }
+
+    pub fn cache_hit_rate(&self) -> f64 {
+        ratio(self.cache_hits, self.cache_probes)
+    }
+
+    // The time is the one of the main thread, all of them run at the same time
+    fn merge(&mut self, other: &SearchStats) {
+        self.nodes += other.nodes;
+        self.qnodes += other.qnodes;
+        self.cutoffs += other.cutoffs;
+        self.first_cutoffs += other.first_cutoffs;
+        self.duck_cutoffs += other.duck_cutoffs;
+        self.cache_probes += other.cache_probes;
+        self.cache_hits += other.cache_hits;
+        self.max_ply = self.max_ply.max(other.max_ply);
+        self.researches += other.researches;
+        self.aspiration_researches += other.aspiration_researches;
+    }
+}
+
+fn ratio(part: u64, total: u64) -> f64 {
+    if total > 0 {
+        part as f64 / total as f64
+    } else {
+        0.0
+    }
+}
+
+impl SearchLimits {
+    pub fn depth(depth: usize) -> Self {
+        SearchLimits { depth: Some(depth), ..SearchLimits::default() }
+    }
+}
+
+impl<'a> SearchContext<'a> {
+    fn new(cache: &'a ZobristCache, settings: &'a SearchSettings, limits: SearchLimits, stop: &'a AtomicBool, helper: bool) -> Self {
+        SearchContext {
+            cache,
+            settings,
+            stop,
+            limits,
+            ordering: MoveOrdering::new(MAX_DEPTH),
+            start: Instant::now(),
+            stats: SearchStats::default(),
+            completed: 0,
+            stopped: false,
+            helper,
+        }
+    }
+
+    fn count_stats(&self, evaluation: &mut Evaluation) {
+        evaluation.stats = SearchStats { elapsed: self.start.elapsed(), ..self.stats };
+    }
+
+    fn visit(&mut self, ply: usize) {
+        self.stats.max_ply = self.stats.max_ply.max(ply);
+    }
+
+    // The first iteration of the main thread always finishes, so there is a movement to play
+    fn may_stop(&self) -> bool {
+        self.helper || self.completed > 0
+    }
+
+    // Looking at the clock is not free, so it is done once every few nodes
+    fn check_limits(&mut self) -> bool {
+        let visited = self.stats.total_nodes();
+        self.stopped |= self.stop.load(atomic::Ordering::Relaxed);
+
+        if let Some(nodes) = self.limits.nodes {
+            self.stopped |= visited >= nodes;
+        }
+
+        if let Some(time) = self.limits.time {
+            if visited & 1023 == 0 {
+                self.stopped |= self.start.elapsed() >= time;
+            }
+        }
+
+        self.stopped
+    }
+}
+
+const FULL_WINDOW: Prune = Prune {
+    alpha: -i32::MAX,
+    beta: i32::MAX,
+};
+
 impl Prune {
     fn invert(&self) -> Self {
         Prune{
@@ -30,77 +257,416 @@ impl Prune {
     }
 }
 
-#[allow(dead_code)]
+/// The best movement for the side to move, searching until some depth
 pub fn search(board: &Board, depth: usize) -> Option<Movement> {
-    let mut cache = ZobristCache::new();
-    search_cached(board, depth, &mut cache)
+    let cache = ZobristCache::new();
+    search_cached(board, depth, &cache, &SearchSettings::default())
 }
 
-#[allow(dead_code)]
+/// Searches until some depth, the score is from the point of view of the side to move
 pub fn evaluate(board: &Board, depth: usize) -> Evaluation {
-    let mut cache = ZobristCache::new();
-    evaluate_cached(board, depth, &mut cache)
+    let cache = ZobristCache::new();
+    evaluate_cached(board, depth, &cache, &SearchSettings::default())
 }
 
-pub fn search_cached(board: &Board, depth: usize, cache: &mut ZobristCache) -> Option<Movement> {
-    let prune = Prune {
-        alpha: -i32::MAX,
-        beta: i32::MAX,
-    };
-    _search(board, depth, prune, cache).movement
+pub fn search_cached(board: &Board, depth: usize, cache: &ZobristCache, settings: &SearchSettings) -> Option<Movement> {
+    evaluate_cached(board, depth, cache, settings).movement
+}
+
+/// Like [`evaluate`], keeping what was learned in the cache for the next searches
+pub fn evaluate_cached(board: &Board, depth: usize, cache: &ZobristCache, settings: &SearchSettings) -> Evaluation {
+    evaluate_limited(board, SearchLimits::depth(depth), cache, settings)
 }
 
-pub fn evaluate_cached(board: &Board, depth: usize, cache: &mut ZobristCache) -> Evaluation {
-    let prune = Prune {
-        alpha: -i32::MAX,
-        beta: i32::MAX,
+pub fn search_limited(board: &Board, limits: SearchLimits, cache: &ZobristCache, settings: &SearchSettings) -> Option<Movement> {
+    evaluate_limited(board, limits, cache, settings).movement
+}
+
+/// Searches until the first limit is reached, unlimited searches stop at the maximum depth
+pub fn evaluate_limited(board: &Board, limits: SearchLimits, cache: &ZobristCache, settings: &SearchSettings) -> Evaluation {
+    evaluate_interruptible(board, limits, cache, settings, &AtomicBool::new(false), &mut |_, _| ())
+}
+
+// The search stops with the best movement found so far once another thread raises the
+// stop flag, and every finished iteration is reported with its depth. The helper
+// threads have a flag of their own, raised when the main thread is done.
+//
+/// Lazy SMP: every helper thread runs its own iterative deepening on the same position,
+/// and the only thing they share is the cache. The main thread finds the results of the
+/// helpers there for free, and half of them start one ply deeper to look at other lines.
+/// Only the main thread result is used, the helpers stop as soon as it is done.
+pub fn evaluate_interruptible(
+    board: &Board,
+    limits: SearchLimits,
+    cache: &ZobristCache,
+    settings: &SearchSettings,
+    stop: &AtomicBool,
+    report: &mut dyn FnMut(usize, &Evaluation),
+) -> Evaluation {
+    cache.new_search();
+    let done = AtomicBool::new(false);
+
+    thread::scope(|scope| {
+        let helpers: Vec<_> = (1..settings.threads).map(|id| {
+            let helper_limits = SearchLimits { depth: limits.depth, ..SearchLimits::default() };
+            let done = &done;
+
+            scope.spawn(move || {
+                let mut context = SearchContext::new(cache, settings, helper_limits, done, true);
+                iterative_deepening(board, 1 + id % 2, &mut context, &mut |_, _| ())
+            })
+        }).collect();
+
+        let mut context = SearchContext::new(cache, settings, limits, stop, false);
+        let mut best = iterative_deepening(board, 1, &mut context, report);
+        done.store(true, atomic::Ordering::Relaxed);
+
+        for helper in helpers {
+            let evaluation = helper.join().unwrap();
+            best.stats.merge(&evaluation.stats);
+        }
+
+        best
+    })
+}
+
+/// A search running in its own thread, for front-ends that keep reading commands meanwhile
+pub struct BackgroundSearch {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+impl BackgroundSearch {
+    /// Searches the board like [`evaluate_interruptible`]. Every finished iteration goes
+    /// to `report` and the result to `finish`, both called from the search thread.
+    pub fn start(
+        board: Board,
+        limits: SearchLimits,
+        cache: Arc<ZobristCache>,
+        settings: SearchSettings,
+        mut report: impl FnMut(usize, &Evaluation) + Send + 'static,
+        finish: impl FnOnce(Evaluation) + Send + 'static,
+    ) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let worker_stop = Arc::clone(&stop);
+
+        let handle = thread::spawn(move || {
+            let evaluation = evaluate_interruptible(&board, limits, &cache, &settings, &worker_stop, &mut report);
+            finish(evaluation);
+        });
+
+        BackgroundSearch { stop, handle }
+    }
+
+    /// Asks the search to finish with the best movement found so far
+    pub fn stop(&self) {
+        self.stop.store(true, atomic::Ordering::Relaxed);
+    }
+
+    /// Waits until the search is over and `finish` was called
+    pub fn join(self) {
+        let _ = self.handle.join();
+    }
+}
+
+// Iterative deepening: searches with depth 1, 2, 3... until a limit is reached.
+// The cache keeps the best movements of each iteration, so the next one starts with
+// a good ordering, and an unfinished iteration is thrown away.
+fn iterative_deepening(board: &Board, first_depth: usize, context: &mut SearchContext, report: &mut dyn FnMut(usize, &Evaluation)) -> Evaluation {
+    let limits = context.limits;
+    let settings = context.settings;
+    let max_depth = limits.depth.unwrap_or(MAX_DEPTH).min(MAX_DEPTH);
+    let mut best = Evaluation::default();
+
+    for depth in first_depth.min(max_depth)..=max_depth {
+        let evaluation = if settings.aspiration && depth > 1 && mate_in(best.score).is_none() {
+            aspiration_search(board, depth, best.score, context)
+        } else {
+            _search(board, depth, 0, FULL_WINDOW, context)
+        };
+
+        if context.stopped {
+            break;
+        }
+
+        // the depth below the root may come from deeper cache entries
+        best = Evaluation { depth, ..evaluation };
+        complete_pv(board, &mut best.pv, depth, context.cache);
+        context.completed = depth;
+        context.count_stats(&mut best);
+        report(depth, &best);
+
+        // a faster win will not show up in a deeper search
+        if best.score > MATE_BOUND {
+            break;
+        }
+
+        // the next iteration takes longer than all the previous ones together
+        if let Some(time) = limits.time {
+            if context.start.elapsed() * 2 >= time {
+                break;
+            }
+        }
+    }
+
+    context.count_stats(&mut best);
+    best
+}
+
+// The score rarely changes much from one iteration to the next, so the root is searched
+// with a small window around the last one. When the score falls outside of it, the window
+// grows on that side until it fits, and too large windows become infinite.
+fn aspiration_search(board: &Board, depth: usize, previous: i32, context: &mut SearchContext) -> Evaluation {
+    let mut window = ASPIRATION_WINDOW;
+    let mut prune = Prune {
+        alpha: previous - window,
+        beta: previous + window,
     };
-    _search(board, depth, prune, cache)
+
+    loop {
+        let evaluation = _search(board, depth, 0, prune, context);
+
+        if context.stopped {
+            return evaluation;
+        }
+
+        let failed_low = evaluation.score <= prune.alpha && prune.alpha != FULL_WINDOW.alpha;
+        let failed_high = evaluation.score >= prune.beta && prune.beta != FULL_WINDOW.beta;
+
+        if !failed_low && !failed_high {
+            return evaluation;
+        }
+
+        context.stats.aspiration_researches += 1;
+        window *= 4;
+
+        if failed_low {
+            prune.alpha = if window > ASPIRATION_LIMIT { FULL_WINDOW.alpha } else { previous - window };
+        }
+
+        if failed_high {
+            prune.beta = if window > ASPIRATION_LIMIT { FULL_WINDOW.beta } else { previous + window };
+        }
+    }
+}
+
+// Lines cut short by the cache are followed through the best movements it remembers.
+// They are checked again, so a key collision never puts an illegal movement in the line.
+fn complete_pv(board: &Board, pv: &mut Vec<Movement>, depth: usize, cache: &ZobristCache) {
+    let mut tmp_board = board.clone();
+
+    for movement in pv.iter() {
+        tmp_board.make_movement(*movement);
+    }
+
+    while pv.len() < depth && tmp_board.outcome().is_none() {
+        let movement = cache.get(&tmp_board)
+            .and_then(|entry| entry.movement)
+            .and_then(|x| Movement::try_movement(&tmp_board, x.origin, x.target, x.duck_target, x.promotion));
+
+        match movement {
+            Some(movement) => {
+                tmp_board.make_movement(movement);
+                pv.push(movement);
+            },
+            None => break,
+        }
+    }
+}
+
+/// An even share of the clock plus most of the increment, but always leaving some time behind
+pub fn time_for_move(remaining: Duration, increment: Duration, moves_to_go: u32) -> Duration {
+    let available = remaining.saturating_sub(MOVE_OVERHEAD);
+    let share = remaining / moves_to_go.max(1) + increment * 3 / 4;
+    share.min(available).max(Duration::from_millis(1))
+}
+
+/// Mates are moves to the king capture, positive when the side to move wins
+pub fn mate_in(score: i32) -> Option<i32> {
+    let plies = MATE - score.abs();
+
+    if score.abs() > MATE_BOUND {
+        Some(score.signum() * (plies + 1) / 2)
+    } else {
+        None
+    }
+}
+
+// The cache keeps mates counted from the position itself, because the same
+// position may show up again at another distance from the root
+fn score_to_cache(score: i32, ply: usize) -> i32 {
+    match score {
+        x if x > MATE_BOUND => x + ply as i32,
+        x if x < -MATE_BOUND => x - ply as i32,
+        x => x,
+    }
+}
+
+fn score_from_cache(score: i32, ply: usize) -> i32 {
+    match score {
+        x if x > MATE_BOUND => x - ply as i32,
+        x if x < -MATE_BOUND => x + ply as i32,
+        x => x,
+    }
 }
 
-fn _search(board: &Board, depth: usize, prune: Prune, cache: &mut ZobristCache) -> Evaluation {
+fn _search(board: &Board, depth: usize, ply: usize, prune: Prune, context: &mut SearchContext) -> Evaluation {
     if depth == 0 {
-        return _evaluate(board);
+        return quiescence(board, ply, prune, context);
     }
-    
-    let mut prune = prune;
-    let mut best = Evaluation { movement: None, score: -i32::MAX, depth:0 };
+
+    context.stats.nodes += 1;
+    context.visit(ply);
+
+    if context.may_stop() && context.check_limits() {
+        return Evaluation::default();
+    }
+
+    if !board.king_exists(board.active_color) {
+        return Evaluation { score: -MATE + ply as i32, ..Evaluation::default() };
+    }
+
+    // the root still needs a movement to play, even if the game is already drawn
+    if board.draw().is_some() {
+        let movement = if ply == 0 { Movement::avaliable_moves(board).first().copied() } else { None };
+        return Evaluation { movement, pv: movement.into_iter().collect(), ..Evaluation::default() };
+    }
+
+    // Mate distance pruning: the best that can happen here is winning right now with no
+    // movements left, and the worst is losing the king right now. So when a shorter mate
+    // was already found elsewhere there is nothing to look for here.
+    let mut prune = Prune {
+        alpha: prune.alpha.max(-MATE + ply as i32),
+        beta: prune.beta.min(MATE - ply as i32),
+    };
+
+    if prune.alpha >= prune.beta {
+        return Evaluation { score: prune.alpha, ..Evaluation::default() };
+    }
+
+    let alpha = prune.alpha;
+    let mut best = Evaluation { score: prune.alpha, ..Evaluation::default() };
+    let mut hash_movement = None;
 
     // One thing to consider here. With the cache enabled the results may be different.
     // I realized it is not wrong, but it is because the depth of the search virtually 
     // increases when a position repeats in the same search.
-    if let Some(evaluation) = cache.get(board) {
-        if (evaluation.score >= prune.beta) && (evaluation.depth >= depth) {
-            return Evaluation{score: prune.beta, ..evaluation};
+    // The root is always searched, the cache only tells which movement goes first there.
+    context.stats.cache_probes += 1;
+    if let Some(entry) = context.cache.get(board) {
+        context.stats.cache_hits += 1;
+        hash_movement = entry.movement;
+        let score = score_from_cache(entry.score, ply);
+        let pv = entry.movement.into_iter().collect();
+        let evaluation = Evaluation { movement: entry.movement, score, depth: entry.depth, pv, ..Evaluation::default() };
+
+        if entry.depth >= depth && ply > 0 {
+            match entry.bound {
+                Bound::Exact => return evaluation,
+                Bound::Lower if score >= prune.beta => return Evaluation{score: prune.beta, ..evaluation},
+                Bound::Upper if score <= prune.alpha => return Evaluation{score: prune.alpha, ..evaluation},
+                _ => (),
+            }
         }
+    }
 
-        if evaluation.depth >= depth {
-            prune.alpha = evaluation.score;        
-            best = evaluation;
+    // Futility: this close to the leaves a quiet movement rarely changes the score by
+    // more than the margin, so when the static score is far from the window the search
+    // either stops right here (reverse futility) or only looks at the forcing movements.
+    let mut futile = false;
+    let settings = context.settings;
+    let mates = prune.alpha.abs() > MATE_BOUND || prune.beta.abs() > MATE_BOUND;
+
+    if depth <= FUTILITY_DEPTH && ply > 0 && !mates {
+        let static_score = count_centipawns(board);
+        let margin = settings.futility_margin * depth as i32;
+
+        if settings.reverse_futility && static_score - margin >= prune.beta {
+            return Evaluation { score: prune.beta, ..Evaluation::default() };
         }
+
+        futile = settings.futility && static_score + margin <= prune.alpha;
     }
 
     let mut simple_movements = Movement::avaliable_moves(board);
-    simple_movements.sort_by_cached_key(|x| -estimate_movement(x));
+    if !context.settings.underpromotions {
+        simple_movements.retain(|x| !x.is_underpromotion());
+    }
+    if context.settings.ordering {
+        simple_movements.sort_by_cached_key(|x| -order_movement(x, ply, &context.ordering));
+    } else {
+        simple_movements.sort_by_cached_key(|x| -estimate_movement(x));
+    }
+
+    // the best movement found last time goes first
+    if let Some(hash_movement) = hash_movement {
+        if let Some(index) = simple_movements.iter().position(|x| same_piece_movement(x, &hash_movement)) {
+            let movement = simple_movements.remove(index);
+            simple_movements.insert(0, movement);
+        }
+    }
     
+    // the king is still here, so being stuck means we won
     if simple_movements.is_empty() {
-        return _evaluate(board);
+        return Evaluation { score: MATE - ply as i32, ..Evaluation::default() };
     }
 
-    for movement in simple_movements {
-        let evaluation = duck_search(board, depth-1, prune.invert(), cache, movement);
+    for (i, movement) in simple_movements.into_iter().enumerate() {
+        // Captures, promotions and attacks on the king are never pruned nor reduced.
+        // Looking for attacks is slow, so it is only done when something may be skipped.
+        let prunable = futile && i > 0;
+        let reducible = settings.reductions && depth >= 3 && i >= settings.late_moves;
+        let forcing = (prunable || reducible) && (!movement.is_quiet() || movement.threatens_king(board));
 
-        if evaluation.score >= prune.beta {
-            best = Evaluation{score: prune.beta, ..evaluation};
-            break
+        if prunable && !forcing {
+            continue;
         }
 
-        let is_mate = evaluation.score >= piece_value(PieceKind::King);
+        let reduction = if reducible && !forcing {
+            if depth >= 5 && i >= 3 * settings.late_moves { 2 } else { 1 }
+        } else {
+            0
+        };
+
+        // Principal variation search: the first movement is probably the best one, so the
+        // others only have to prove they are worse, which a null window does much faster.
+        // The few that turn out better are searched again with the full window.
+        let evaluation = if i == 0 {
+            search_movement(board, depth-1, ply+1, prune, context, movement)
+        } else {
+            let window = if settings.pvs { Prune { alpha: prune.alpha, beta: prune.alpha + 1 } } else { prune };
+            let mut evaluation = search_movement(board, depth-1-reduction, ply+1, window, context, movement);
+
+            // a reduced movement that looks good must prove it with the whole depth
+            if reduction > 0 && evaluation.score > prune.alpha && !context.stopped {
+                context.stats.researches += 1;
+                evaluation = search_movement(board, depth-1, ply+1, window, context, movement);
+            }
 
-        if is_mate && (evaluation.depth < best.depth) {
-            prune.alpha = evaluation.score;
-            best = evaluation;
-            break;
+            if settings.pvs && evaluation.score > prune.alpha && evaluation.score < prune.beta && !context.stopped {
+                context.stats.researches += 1;
+                evaluation = search_movement(board, depth-1, ply+1, prune, context, movement);
+            }
+
+            evaluation
+        };
+
+        // the search was interrupted, this result is worthless and must not be cached
+        if context.stopped {
+            return best;
+        }
+
+        if evaluation.score >= prune.beta {
+            context.stats.cutoffs += 1;
+            if i == 0 {
+                context.stats.first_cutoffs += 1;
+            }
+
+            if let (true, Some(refutation)) = (context.settings.ordering, evaluation.movement) {
+                context.ordering.store_cutoff(ply, depth, &refutation);
+            }
+            best = Evaluation{score: prune.beta, ..evaluation};
+            break
         }
 
         if evaluation.score > prune.alpha {
@@ -109,30 +675,130 @@ fn _search(board: &Board, depth: usize, prune: Prune, cache: &mut ZobristCache)
         }
     }
 
+    let bound = if best.score >= prune.beta {
+        Bound::Lower
+    } else if best.score > alpha {
+        Bound::Exact
+    } else {
+        Bound::Upper
+    };
+
     best.depth += 1;
-    cache.insert(board, best);
+
+    context.cache.insert(board, depth, score_to_cache(best.score, ply), bound, best.movement);
     best
 }
 
+// Searches the enemy replies, the prune is the one of the side making the movement
+fn search_movement(board: &Board, depth: usize, ply: usize, prune: Prune, context: &mut SearchContext, movement: Movement) -> Evaluation {
+    match context.settings.ducks {
+        DuckSearch::Heuristic => duck_search(board, depth, ply, prune.invert(), context, movement),
+        DuckSearch::Full => full_duck_search(board, depth, ply, prune.invert(), context, movement),
+    }
+}
+
 fn _evaluate(board: &Board) -> Evaluation {
     Evaluation {
         movement: None,
         score: count_centipawns(board),
-        depth: 0,
+        ..Evaluation::default()
+    }
+}
+
+// Captures and promotions are played until the position is quiet, so the score never
+// comes from the middle of an exchange. The side to move may also "stand pat" and keep
+// the static evaluation, since a quiet movement is almost always available.
+fn quiescence(board: &Board, ply: usize, prune: Prune, context: &mut SearchContext) -> Evaluation {
+    context.stats.qnodes += 1;
+    context.visit(ply);
+
+    if context.may_stop() && context.check_limits() {
+        return Evaluation::default();
+    }
+
+    if !board.king_exists(board.active_color) {
+        return Evaluation { score: -MATE + ply as i32, ..Evaluation::default() };
+    }
+
+    if board.draw().is_some() {
+        return Evaluation::default();
+    }
+
+    let mut prune = prune;
+    let stand_pat = _evaluate(board);
+
+    if stand_pat.score >= prune.beta {
+        return Evaluation { score: prune.beta, ..stand_pat };
+    }
+
+    let mut best = Evaluation { score: prune.alpha, ..Evaluation::default() };
+    if stand_pat.score > prune.alpha {
+        prune.alpha = stand_pat.score;
+        best = stand_pat.clone();
+    }
+
+    let mut loud_movements = Movement::avaliable_moves(board);
+    loud_movements.retain(|x| {
+        !x.is_quiet()
+        && (context.settings.underpromotions || !x.is_underpromotion())
+    });
+    loud_movements.sort_by_cached_key(|x| -estimate_movement(x));
+
+    for movement in loud_movements {
+        // delta pruning: even winning the piece for free would not be enough
+        let mut gain = movement.captured.map_or(0, |x| piece_value(x.kind));
+        if let Some(promotion) = movement.promotion {
+            gain += piece_value(promotion) - piece_value(PieceKind::Pawn);
+        }
+
+        if stand_pat.score + gain + DELTA_MARGIN <= prune.alpha {
+            continue;
+        }
+
+        // the ducks are placed like in the main search, blocking the best recapture
+        let evaluation = duck_search(board, 0, ply+1, prune.invert(), context, movement);
+
+        if context.stopped {
+            return best;
+        }
+
+        if evaluation.score >= prune.beta {
+            return Evaluation { score: prune.beta, ..evaluation };
+        }
+
+        if evaluation.score > prune.alpha {
+            prune.alpha = evaluation.score;
+            best = evaluation;
+        }
     }
+
+    best
 }
 
-fn duck_search(board: &Board, depth: usize, prune: Prune, cache: &mut ZobristCache, movement: Movement) -> Evaluation {
+fn duck_search(board: &Board, depth: usize, ply: usize, prune: Prune, context: &mut SearchContext, movement: Movement) -> Evaluation {
     let mut best = movement;
     let mut tmp_board = board.copy_movement(movement);
-    let mut threat = _search(&tmp_board, depth, prune, cache);
+    let mut threat = _search(&tmp_board, depth, ply, prune, context);
 
     if let Some(reaction) = threat.movement {
-        for duck_target in intercept(board, &reaction) {
+        let mut ducks = intercept(board, &movement, &reaction);
+        if context.settings.ordering {
+            ducks.sort_by_cached_key(|&duck_target| -context.ordering.duck_history(&Movement {duck_target, ..movement}));
+        }
+
+        for duck_target in ducks {
+            // the enemy reply is already too good for us, no need to look for other ducks
+            if threat.score <= prune.alpha {
+                context.stats.duck_cutoffs += 1;
+                break;
+            }
+
+            // a duck only matters if it makes the threat smaller
+            let threat_prune = Prune { beta: prune.beta.min(threat.score), ..prune };
             let alternative_movement = Movement {duck_target, ..movement};
             tmp_board = board.copy_movement(alternative_movement);
-            let alternative_threat = _search(&tmp_board, depth, prune, cache);
-            
+            let alternative_threat = _search(&tmp_board, depth, ply, threat_prune, context);
+
             if alternative_threat.score < threat.score {
                 threat = alternative_threat;
                 best = alternative_movement;
@@ -140,10 +806,96 @@ fn duck_search(board: &Board, depth: usize, prune: Prune, cache: &mut ZobristCac
         }
     }
 
+    let mut pv = vec![best];
+    pv.extend(threat.pv);
+
+    Evaluation {
+        movement: Some(best),
+        score: -threat.score,
+        depth: threat.depth,
+        pv,
+        ..Evaluation::default()
+    }
+}
+
+// Tries every legal duck square instead of guessing the enemy threat.
+// Much slower, but it does not miss the duck blocking two pieces at once.
+fn full_duck_search(board: &Board, depth: usize, ply: usize, prune: Prune, context: &mut SearchContext, movement: Movement) -> Evaluation {
+    let mut threat_prune = prune;
+    let mut best = movement;
+    let mut threat = Evaluation { score: i32::MAX, ..Evaluation::default() };
+
+    let mut duck_movements = Movement::duck_moves(board, &movement);
+    if context.settings.ordering {
+        duck_movements.sort_by_cached_key(|x| -estimate_duck(board, x) - context.ordering.duck_history(x));
+    } else {
+        duck_movements.sort_by_cached_key(|x| -estimate_duck(board, x));
+    }
+
+    for alternative_movement in duck_movements {
+        let tmp_board = board.copy_movement(alternative_movement);
+        let alternative_threat = _search(&tmp_board, depth, ply, threat_prune, context);
+
+        if alternative_threat.score < threat.score {
+            threat = alternative_threat;
+            best = alternative_movement;
+        }
+
+        // the enemy reply is already too good for us, no need to look for worse ducks
+        if threat.score <= threat_prune.alpha {
+            context.stats.duck_cutoffs += 1;
+            break;
+        }
+
+        threat_prune.beta = threat_prune.beta.min(threat.score);
+    }
+
+    let mut pv = vec![best];
+    pv.extend(threat.pv);
+
     Evaluation {
         movement: Some(best),
         score: -threat.score,
         depth: threat.depth,
+        pv,
+        ..Evaluation::default()
+    }
+}
+
+// Ducks close to the enemy king or to the center tend to be the most annoying
+fn estimate_duck(board: &Board, movement: &Movement) -> i32 {
+    let Position(x, y) = movement.duck_target;
+    let mut score = x*(7-x) + y*(7-y);
+
+    if movement.duck_target == movement.origin {
+        score += 100;
+    }
+
+    let enemy = movement.color.invert();
+    let enemy_king = board.ocuppied_squares().find(|x| x.kind == PieceKind::King && x.color == enemy);
+
+    if let Some(king) = enemy_king {
+        let distance = (king.pos.0 - x).abs().max((king.pos.1 - y).abs());
+        score += 4 * (8 - distance);
+    }
+
+    score
+}
+
+fn same_piece_movement(a: &Movement, b: &Movement) -> bool {
+    a.origin == b.origin && a.target == b.target && a.promotion == b.promotion
+}
+
+// Captures and promotions come first, then the killers of this ply,
+// then the quiet movements that caused the most cutoffs so far
+fn order_movement(movement: &Movement, ply: usize, ordering: &MoveOrdering) -> i32 {
+    if !movement.is_quiet() {
+        return CAPTURE_ORDER + estimate_movement(movement);
+    }
+
+    match ordering.killer(ply, movement) {
+        Some(slot) => KILLER_ORDER - slot as i32,
+        None => ordering.history(movement) + estimate_movement(movement),
     }
 }
 
@@ -165,14 +917,20 @@ fn estimate_movement(movement: &Movement) -> i32 {
     score
 }
 
-fn intercept(board: &Board, threat: &Movement) -> Vec<Position> {
+// Duck squares that stop the enemy threat. They must be free once our movement is done,
+// and the duck is never allowed to stay where it is.
+fn intercept(board: &Board, movement: &Movement, threat: &Movement) -> Vec<Position> {
     let duck = match threat.moved {
+        PieceKind::King if threat.is_castle() => {
+            intercept_slide(board, movement, threat)
+        },
+
         PieceKind::Knight | PieceKind::King | PieceKind::Pawn => {
-            intercept_jump(board, threat)
+            intercept_jump(board, movement, threat)
         },
         
         PieceKind::Rook | PieceKind::Bishop | PieceKind::Queen => {
-            intercept_slide(board, threat)
+            intercept_slide(board, movement, threat)
         },
         
         PieceKind::Duck => {
@@ -182,7 +940,7 @@ fn intercept(board: &Board, threat: &Movement) -> Vec<Position> {
     
     let mut ducks = Vec::<Position>::new();
 
-    if board.get_square(threat.duck_target).is_none() {
+    if movement.duck_is_free(board, threat.duck_target) {
         ducks.push(threat.duck_target);
     }
     
@@ -193,22 +951,22 @@ fn intercept(board: &Board, threat: &Movement) -> Vec<Position> {
     ducks
 }
 
-fn intercept_jump(board: &Board, movement: &Movement) -> Option<Position> {
-    if board.get_square(movement.target).is_none() {
-        Some(movement.target)
+fn intercept_jump(board: &Board, movement: &Movement, threat: &Movement) -> Option<Position> {
+    if movement.duck_is_free(board, threat.target) {
+        Some(threat.target)
     } else {
         None
     }
 }
 
-fn intercept_slide(board: &Board, movement: &Movement) -> Option<Position> {
-    let dx = match (movement.target.0 - movement.origin.0).cmp(&0) {
+fn intercept_slide(board: &Board, movement: &Movement, threat: &Movement) -> Option<Position> {
+    let dx = match (threat.target.0 - threat.origin.0).cmp(&0) {
         Ordering::Greater => 1,
         Ordering::Less => -1,
         Ordering::Equal => 0,
     };
 
-    let dy = match (movement.target.1 - movement.origin.1).cmp(&0) {
+    let dy = match (threat.target.1 - threat.origin.1).cmp(&0) {
         Ordering::Greater => 1,
         Ordering::Less => -1,
         Ordering::Equal => 0,
@@ -217,12 +975,12 @@ fn intercept_slide(board: &Board, movement: &Movement) -> Option<Position> {
     // Usually you have 2 ways to block a movement:
     // puting the duck next to the atacker (best)
     // or puting the duck next to the victm (if the other option already has a duck)
-    let pos_1 = Position(movement.origin.0 + dx, movement.origin.1 + dy);
-    let pos_2 = Position(movement.target.0 - dx, movement.target.1 - dy);
+    let pos_1 = Position(threat.origin.0 + dx, threat.origin.1 + dy);
+    let pos_2 = Position(threat.target.0 - dx, threat.target.1 - dy);
 
-    if board.get_square(pos_1).is_none() {
+    if movement.duck_is_free(board, pos_1) {
         Some(pos_1)
-    } else if board.get_square(pos_2).is_none() {
+    } else if movement.duck_is_free(board, pos_2) {
         Some(pos_2)
     } else {
         None
@@ -236,7 +994,7 @@ mod tests {
 
     #[test]
     fn test_obvious() {
-        let board = Board::from_fen("4k3/8/5r2/2KN4/8/8/8/8 w - - 0 1");
+        let board = Board::from_fen("4k3/8/5r2/2KN4/8/8/8/8 w - - 0 1").unwrap();
         let best_move = search(&board, 2);
         
         if best_move.is_none() {
@@ -250,7 +1008,7 @@ mod tests {
     
     #[test]
     fn test_forks() {
-        let board = Board::from_fen("4k3/8/4q3/2KN4/8/8/8/8 w - - 0 1");
+        let board = Board::from_fen("4k3/8/4q3/2KN4/8/8/8/8 w - - 0 1").unwrap();
         let best_move = search(&board, 4);
         println!("{:?}", board);
         
@@ -269,7 +1027,7 @@ mod tests {
 
     #[test]
     fn test_ducktics_1() {
-        let board = Board::from_fen("8/3*4/8/8/8/4K3/8/7k w - - 0 1 q");
+        let board = Board::from_fen("8/3*4/8/8/8/4K3/8/7k w - - 0 1 q").unwrap();
         let best_move = search(&board, 4);
 
         if best_move.is_none() {
@@ -284,7 +1042,7 @@ mod tests {
 
     #[test]
     fn test_ducktics_2() {
-        let board = Board::from_fen("3r3r/pp6/2pk1pp1/3p4/5P1p/P5nP/1P4PK/2RB*q2 b - - 0 1 q");
+        let board = Board::from_fen("3r3r/pp6/2pk1pp1/3p4/5P1p/P5nP/1P4PK/2RB*q2 b - - 0 1 q").unwrap();
         let best_move = search(&board, 4);
 
         if best_move.is_none() {
@@ -298,7 +1056,7 @@ mod tests {
 
     #[test]
     fn test_ducktics_3() {
-        let board = Board::from_fen("r4rk1/p4ppp/2nb4/3p4/3q*1n1/1PN4P/1BP2PP1/3RQRK1 b - - 0 1 q");
+        let board = Board::from_fen("r4rk1/p4ppp/2nb4/3p4/3q*1n1/1PN4P/1BP2PP1/3RQRK1 b - - 0 1 q").unwrap();
         let best_move = search(&board, 4);
 
         if best_move.is_none() {
@@ -313,7 +1071,7 @@ mod tests {
 
     #[test]
     fn test_ducktics_4() {
-        let board = Board::from_fen("2kr4/p4p2/2p2p2/2p5/1PP5/2b2q2/P4*2/5KR1 b - - 0 1 q");
+        let board = Board::from_fen("2kr4/p4p2/2p2p2/2p5/1PP5/2b2q2/P4*2/5KR1 b - - 0 1 q").unwrap();
         let best_move = search(&board, 4);
 
         if best_move.is_none() {
@@ -327,7 +1085,7 @@ mod tests {
 
     #[test]
     fn test_ducktics_5() {
-        let board = Board::from_fen("6k1/5p2/6*1/6QP/4B3/8/5P2/5K2 w - - 0 1 q");
+        let board = Board::from_fen("6k1/5p2/6*1/6QP/4B3/8/5P2/5K2 w - - 0 1 q").unwrap();
         let best_move = dbg!(search(&board, 4));
 
         if best_move.is_none() {
@@ -342,7 +1100,7 @@ mod tests {
 
     #[test]
     fn test_ducktics_6() {
-        let board = Board::from_fen("kb*5/p2B4/8/8/8/8/8/7K w - - 0 1");
+        let board = Board::from_fen("kb*5/p2B4/8/8/8/8/8/7K w - - 0 1").unwrap();
         let best_move = dbg!(search(&board, 4));
 
         if best_move.is_none() {
@@ -357,7 +1115,7 @@ mod tests {
 
     #[test]
     fn test_ducktics_7() {
-        let board = Board::from_fen("rnbqkb1r/ppp1pppp/2*2n2/1B1p3Q/4P3/8/PPPP1PPP/RNB1K1NR b KQkq - 0 1");
+        let board = Board::from_fen("rnbqkb1r/ppp1pppp/2*2n2/1B1p3Q/4P3/8/PPPP1PPP/RNB1K1NR b KQkq - 0 1").unwrap();
         let best_move = dbg!(search(&board, 4));
 
         if best_move.is_none() {
@@ -372,7 +1130,7 @@ mod tests {
 
     #[test]
     fn test_ducktics_8() {
-        let board = Board::from_fen("r3kb1r/pp1bpp1p/2np2p1/2pN4/4P3/3PBB1P/PqPQ1PP1/R*2K2R w KQkq - 0 1");
+        let board = Board::from_fen("r3kb1r/pp1bpp1p/2np2p1/2pN4/4P3/3PBB1P/PqPQ1PP1/R*2K2R w KQkq - 0 1").unwrap();
         let best_move = dbg!(search(&board, 4));
 
         if best_move.is_none() {
@@ -387,7 +1145,7 @@ mod tests {
 
     #[test]
     fn shortest_duckmate() {
-        let board = Board::from_fen("k7/2N5/K7/8/5*2/5q2/4P3/8 w - - 0 1");
+        let board = Board::from_fen("k7/2N5/K7/8/5*2/5q2/4P3/8 w - - 0 1").unwrap();
         let best_move = dbg!(search(&board, 4));
 
         if best_move.is_none() {
@@ -398,4 +1156,201 @@ mod tests {
         assert_eq!(best_move.origin, Position::from_str("C7").unwrap());
         assert_eq!(best_move.target, Position::from_str("A8").unwrap());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn no_moves_is_a_win() {
+        // white is walled in by its own pieces and the duck
+        let board = Board::from_fen("7k/8/8/8/8/1p1p4/*P1P4/KRB5 w - - 0 1").unwrap();
+        let evaluation = evaluate(&board, 2);
+
+        assert!(evaluation.movement.is_none());
+        assert_eq!(evaluation.score, MATE);
+    }
+
+    #[test]
+    fn drawn_root_has_a_movement() {
+        let board = Board::from_fen("4k3/8/8/8/8/8/4P3/4K3 w - - 100 80").unwrap();
+        let evaluation = evaluate(&board, 2);
+
+        assert_eq!(evaluation.score, 0);
+        assert!(evaluation.movement.is_some_and(|x| Movement::try_movement(&board, x.origin, x.target, x.duck_target, x.promotion).is_some()));
+    }
+
+    #[test]
+    fn mate_distance() {
+        // the king is taken right away instead of some moves later
+        let board = Board::from_fen("k7/2N5/K7/8/5*2/5q2/4P3/8 w - - 0 1").unwrap();
+        let evaluation = evaluate(&board, 4);
+        assert_eq!(evaluation.score, MATE - 1);
+        assert_eq!(mate_in(evaluation.score), Some(1));
+
+        // the black king must walk into the white one
+        let mut board = Board::from_fen("8/3*4/8/8/8/4K3/8/7k w - - 0 1 q").unwrap();
+        let evaluation = evaluate(&board, 4);
+        assert_eq!(mate_in(evaluation.score), Some(2));
+
+        board.make_movement(evaluation.movement.unwrap());
+        let evaluation = evaluate(&board, 4);
+        assert_eq!(mate_in(evaluation.score), Some(-1));
+        assert_eq!(mate_in(0), None);
+    }
+
+    #[test]
+    fn quiescence_sees_recaptures() {
+        // taking the pawn loses the queen, even if the search ends right after it
+        let board = Board::from_fen("4k3/8/2p5/3p4/8/8/8/3QK3 w - - 0 1").unwrap();
+        let evaluation = evaluate(&board, 1);
+
+        assert_ne!(evaluation.movement.unwrap().target, Position::from_str("D5").unwrap());
+        assert!(evaluation.stats.qnodes > 0);
+    }
+
+    #[test]
+    fn principal_variation() {
+        let mut board = Board::arranged();
+        let evaluation = evaluate(&board, 4);
+
+        assert!(evaluation.pv.len() >= 4);
+        assert!(same_piece_movement(&evaluation.pv[0], &evaluation.movement.unwrap()));
+
+        // every movement of the line must be playable after the previous ones
+        for movement in evaluation.pv {
+            let replay = Movement::try_movement(&board, movement.origin, movement.target, movement.duck_target, movement.promotion);
+            assert!(replay.is_some());
+            board.make_movement(movement);
+        }
+    }
+
+    const DUCKTICS: [&str; 8] = [
+        "8/3*4/8/8/8/4K3/8/7k w - - 0 1 q",
+        "3r3r/pp6/2pk1pp1/3p4/5P1p/P5nP/1P4PK/2RB*q2 b - - 0 1 q",
+        "r4rk1/p4ppp/2nb4/3p4/3q*1n1/1PN4P/1BP2PP1/3RQRK1 b - - 0 1 q",
+        "2kr4/p4p2/2p2p2/2p5/1PP5/2b2q2/P4*2/5KR1 b - - 0 1 q",
+        "6k1/5p2/6*1/6QP/4B3/8/5P2/5K2 w - - 0 1 q",
+        "kb*5/p2B4/8/8/8/8/8/7K w - - 0 1",
+        "rnbqkb1r/ppp1pppp/2*2n2/1B1p3Q/4P3/8/PPPP1PPP/RNB1K1NR b KQkq - 0 1",
+        "r3kb1r/pp1bpp1p/2np2p1/2pN4/4P3/3PBB1P/PqPQ1PP1/R*2K2R w KQkq - 0 1",
+    ];
+
+    // Every feature visits fewer positions in the tactics above than the search without it
+    #[test]
+    fn features_save_nodes() {
+        let nodes = |settings: &SearchSettings| -> u64 {
+            DUCKTICS.iter()
+                .map(|fen| Board::from_fen(fen).unwrap())
+                .map(|board| evaluate_cached(&board, 4, &ZobristCache::new(), settings).stats.total_nodes())
+                .sum()
+        };
+
+        let features = [
+            ("ordering", SearchSettings { ordering: false, ..SearchSettings::default() }),
+            ("pvs", SearchSettings { pvs: false, aspiration: false, ..SearchSettings::default() }),
+            ("pruning", SearchSettings { reductions: false, futility: false, reverse_futility: false, ..SearchSettings::default() }),
+        ];
+
+        let with = nodes(&SearchSettings::default());
+
+        for (feature, without) in features {
+            assert!(with < nodes(&without), "{} does not save nodes", feature);
+        }
+    }
+
+    #[test]
+    fn threads_share_the_cache() {
+        let board = Board::from_fen("6k1/5p2/6*1/6QP/4B3/8/5P2/5K2 w - - 0 1 q").unwrap();
+        let settings = SearchSettings { threads: 4, ..SearchSettings::default() };
+        let cache = ZobristCache::new();
+        let evaluation = evaluate_cached(&board, 4, &cache, &settings);
+        let best_move = evaluation.movement.unwrap();
+
+        assert_eq!(best_move.origin, Position(6, 4));
+        assert_eq!(best_move.target, Position(5, 5));
+        assert!(cache.get(&board).is_some());
+    }
+
+    #[test]
+    fn stop_flag() {
+        let board = Board::arranged();
+        let cache = ZobristCache::new();
+        let mut iterations = 0;
+
+        // even when stopped right away, the first iteration finishes to give a movement
+        let stop = AtomicBool::new(true);
+        let evaluation = evaluate_interruptible(&board, SearchLimits::default(), &cache, &SearchSettings::default(), &stop, &mut |_, _| iterations += 1);
+
+        assert!(evaluation.movement.is_some());
+        assert_eq!(iterations, 1);
+
+        // the helpers are stopped without touching the flag of the caller
+        let stop = AtomicBool::new(false);
+        let settings = SearchSettings { threads: 3, ..SearchSettings::default() };
+        evaluate_interruptible(&board, SearchLimits::depth(2), &cache, &settings, &stop, &mut |_, _| ());
+        assert!(!stop.load(atomic::Ordering::Relaxed));
+    }
+
+    #[test]
+    fn root_ignores_the_cache() {
+        let board = Board::arranged();
+        let cache = ZobristCache::new();
+        evaluate_cached(&board, 3, &cache, &SearchSettings::default());
+
+        // the second search still looks at the root movements in every iteration
+        let mut depths = Vec::new();
+        let evaluation = evaluate_interruptible(&board, SearchLimits::depth(3), &cache, &SearchSettings::default(), &AtomicBool::new(false), &mut |depth, evaluation| {
+            depths.push((depth, evaluation.depth, evaluation.stats.max_ply > 0));
+        });
+
+        assert_eq!(depths, vec![(1, 1, true), (2, 2, true), (3, 3, true)]);
+        assert_eq!(evaluation.depth, 3);
+    }
+
+    #[test]
+    fn search_stats() {
+        let board = Board::from_fen("3r3r/pp6/2pk1pp1/3p4/5P1p/P5nP/1P4PK/2RB*q2 b - - 0 1 q").unwrap();
+        let stats = evaluate(&board, 4).stats;
+
+        assert!(stats.nodes > 0 && stats.qnodes > 0);
+        assert!(stats.cutoffs >= stats.first_cutoffs && stats.first_cutoffs > 0);
+        assert!(stats.cache_probes >= stats.cache_hits && stats.cache_hits > 0);
+        assert!(stats.max_ply > 4);
+        assert!(stats.first_cutoff_rate() <= 1.0);
+    }
+
+    #[test]
+    fn full_ducks_obvious() {
+        let board = Board::from_fen("4k3/8/5r2/2KN4/8/8/8/8 w - - 0 1").unwrap();
+        let settings = SearchSettings { ducks: DuckSearch::Full, ..SearchSettings::default() };
+        let best_move = search_cached(&board, 2, &ZobristCache::new(), &settings).unwrap();
+
+        assert_eq!(best_move.origin, Position(3, 4));
+        assert_eq!(best_move.target, Position(5, 5));
+    }
+
+    #[test]
+    fn full_ducks_ducktics_1() {
+        let board = Board::from_fen("8/3*4/8/8/8/4K3/8/7k w - - 0 1 q").unwrap();
+        let settings = SearchSettings { ducks: DuckSearch::Full, ..SearchSettings::default() };
+        let best_move = search_cached(&board, 4, &ZobristCache::new(), &settings).unwrap();
+
+        assert_eq!(best_move.origin, Position(4, 2));
+        assert_eq!(best_move.target, Position(5, 1));
+        assert_eq!(best_move.duck_target, Position(7, 1));
+    }
+
+    #[test]
+    fn limited_search() {
+        let board = Board::arranged();
+        let settings = SearchSettings::default();
+
+        let limits = SearchLimits { nodes: Some(1), ..SearchLimits::default() };
+        let evaluation = evaluate_limited(&board, limits, &ZobristCache::new(), &settings);
+        assert!(evaluation.movement.is_some());
+        assert_eq!(evaluation.depth, 1);
+
+        let limits = SearchLimits { time: Some(Duration::from_millis(50)), ..SearchLimits::default() };
+        let start = Instant::now();
+        let evaluation = evaluate_limited(&board, limits, &ZobristCache::new(), &settings);
+        assert!(evaluation.movement.is_some());
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}
@@ -82,6 +82,7 @@ static DUCK_TABLE: [i32; 64] = [
 0, 0, 0, 0, 0, 0, 0, 0,
 ];
 
+/// The static evaluation of the position, in centipawns for the side to move
 pub fn count_centipawns(board: &Board) -> i32 {
     let mut score: i32 = 0;
 
@@ -1,25 +1,50 @@
-use crate::board::Board;
+use crate::board::{Board, Castle};
 use crate::pieces::PieceKind;
 use crate::pieces::Position;
 use crate::pieces::Piece;
 use crate::pieces::Color;
+use std::fmt;
 
 
-pub fn fen_to_board(notation: &str) -> Board {
+/// Why a FEN could not be read
+#[derive(Clone, Debug, PartialEq)]
+pub enum FenError {
+    MissingField(&'static str),
+    InvalidPiece(char),
+    InvalidRankLength(usize),
+    TooManyRanks,
+    TooFewRanks,
+    MissingKing(Color),
+    MultipleDucks,
+    InvalidColor(String),
+    InvalidCastle(String),
+    InvalidEnPassant(String),
+    InvalidClock(String),
+}
+
+/// The duck is written as a '*', the same way pychess and Fairy-Stockfish write it.
+/// Only the pieces and the color are required, the other fields have the usual defaults,
+/// and extra tokens after the fullmove number (some duck tools append one) are ignored.
+pub fn fen_to_board(notation: &str) -> Result<Board, FenError> {
     let mut board = Board::new();
-    let mut notation_parts = notation.split(' ');
+    let mut notation_parts = notation.split_whitespace();
 
-    let pieces_part = notation_parts.next().unwrap();
-    let color_part = notation_parts.next().unwrap();
-    // let castle_part = notation_parts.nth(0).unwrap();
-    // let en_passant_part = notation_parts.nth(0).unwrap();
+    let pieces_part = notation_parts.next().ok_or(FenError::MissingField("pieces"))?;
+    let color_part = notation_parts.next().ok_or(FenError::MissingField("active color"))?;
+    let castle_part = notation_parts.next().unwrap_or("-");
+    let en_passant_part = notation_parts.next().unwrap_or("-");
+    let halfmove_part = notation_parts.next().unwrap_or("0");
+    let fullmove_part = notation_parts.next().unwrap_or("1");
 
-    _pieces_decode(&mut board, pieces_part);
-    _color_decode(&mut board, color_part);
-    // _castle_decode(&mut board, castle_part);
-    // _en_passant_decode(&mut board, en_passant_part);
+    _pieces_decode(&mut board, pieces_part)?;
+    _color_decode(&mut board, color_part)?;
+    _castle_decode(&mut board, castle_part)?;
+    _en_passant_decode(&mut board, en_passant_part)?;
+    board.halfmove_clock = _clock_decode(halfmove_part)?;
+    board.fullmove_number = _clock_decode(fullmove_part)?.max(1);
+    board.refresh_key();
 
-    board
+    Ok(board)
 }
 
 pub fn board_to_fen(board: &Board) -> String {
@@ -28,8 +53,8 @@ pub fn board_to_fen(board: &Board) -> String {
             _color_encode(board),
             _castle_encode(board),
             _en_passant_encode(board),
-            board.move_counter,
-            1,
+            board.halfmove_clock,
+            board.fullmove_number,
         )
 }
 
@@ -63,15 +88,20 @@ fn _pieces_encode(board: &Board) -> String {
     notation
 }
 
-fn _pieces_decode(board: &mut Board, notation_part: &str) {
+fn _pieces_decode(board: &mut Board, notation_part: &str) -> Result<(), FenError> {
     let mut x: i32 = 0;
     let mut y: i32 = 0;
+    let rank = |y: i32| (8 - y) as usize;
 
     for c in notation_part.chars() {
         match c {
-            ' ' => break,
-
             '/' => {
+                if x != 8 {
+                    return Err(FenError::InvalidRankLength(rank(y)));
+                }
+                if y == 7 {
+                    return Err(FenError::TooManyRanks);
+                }
                 x = 0;
                 y += 1;
             },
@@ -79,16 +109,45 @@ fn _pieces_decode(board: &mut Board, notation_part: &str) {
             '1'..='8' => {
                 let int_c: i32 = c as i32 - 0x30;
                 x += int_c;
+
+                if x > 8 {
+                    return Err(FenError::InvalidRankLength(rank(y)));
+                }
             },
             
             _ => {
+                if x >= 8 {
+                    return Err(FenError::InvalidRankLength(rank(y)));
+                }
+
                 let pos = Position(x, 7-y);
-                let square = fen_to_piece(c, pos);
+                let square = fen_to_piece(c, pos)?;
+
+                if square.kind == PieceKind::Duck && board.duck.is_some() {
+                    return Err(FenError::MultipleDucks);
+                }
+
                 board.set_square(square);
                 x += 1;
             }
         }
     }
+
+    if y < 7 {
+        return Err(FenError::TooFewRanks);
+    }
+
+    if x != 8 {
+        return Err(FenError::InvalidRankLength(rank(y)));
+    }
+
+    for color in [Color::White, Color::Black] {
+        if !board.king_exists(color) {
+            return Err(FenError::MissingKing(color));
+        }
+    }
+
+    Ok(())
 }
 
 fn _color_encode(board: &Board) -> String {
@@ -99,27 +158,94 @@ fn _color_encode(board: &Board) -> String {
     }
 }
 
-fn _color_decode(board: &mut Board, notation_part: &str) {
+fn _color_decode(board: &mut Board, notation_part: &str) -> Result<(), FenError> {
     match notation_part {
         "w" => board.active_color = Color::White,
         "b" => board.active_color = Color::Black,
-        _ => panic!("Invalid color")
+        _ => return Err(FenError::InvalidColor(notation_part.to_string())),
+    }
+    Ok(())
+}
+
+fn _castle_encode(board: &Board) -> String {
+    let mut notation = String::new();
+
+    if board.white_castle.short {
+        notation.push('K');
+    }
+    if board.white_castle.long {
+        notation.push('Q');
+    }
+    if board.black_castle.short {
+        notation.push('k');
+    }
+    if board.black_castle.long {
+        notation.push('q');
+    }
+
+    if notation.is_empty() {
+        notation.push('-');
+    }
+
+    notation
+}
+
+// Fairy-Stockfish may write the castling rights with the rook files (Shredder-FEN),
+// so "HAha" is accepted as well as "KQkq".
+fn _castle_decode(board: &mut Board, notation_part: &str) -> Result<(), FenError> {
+    board.white_castle = Castle::none();
+    board.black_castle = Castle::none();
+
+    if notation_part == "-" {
+        return Ok(());
+    }
+
+    for c in notation_part.chars() {
+        match c {
+            'K' | 'H' => board.white_castle.short = true,
+            'Q' | 'A' => board.white_castle.long = true,
+            'k' | 'h' => board.black_castle.short = true,
+            'q' | 'a' => board.black_castle.long = true,
+            _ => return Err(FenError::InvalidCastle(notation_part.to_string())),
+        }
     }
+
+    Ok(())
 }
 
-fn _castle_encode(_board: &Board) -> String {
-    String::from("-")
+fn _en_passant_encode(board: &Board) -> String {
+    match board.en_passant {
+        Some(pos) => pos.to_string(),
+        None => String::from("-"),
+    }
 }
 
-// fn _castle_decode(board: &mut Board, notation_part: &str) {
-// }
+fn _en_passant_decode(board: &mut Board, notation_part: &str) -> Result<(), FenError> {
+    if notation_part == "-" {
+        board.en_passant = None;
+        return Ok(());
+    }
+
+    // the pawn that can be taken just moved, so the square is behind the side not to move
+    let rank = match board.active_color {
+        Color::White => 5,
+        _ => 2,
+    };
 
-fn _en_passant_encode(_board: &Board) -> String {
-    String::from("-")
+    match Position::from_str(notation_part) {
+        Some(pos) if pos.1 == rank => {
+            board.en_passant = Some(pos);
+            Ok(())
+        },
+        _ => Err(FenError::InvalidEnPassant(notation_part.to_string())),
+    }
 }
 
-// fn _en_passant_decode(board: &mut Board, notation_part: &str) {
-// }
+fn _clock_decode(notation_part: &str) -> Result<usize, FenError> {
+    notation_part
+        .parse::<usize>()
+        .map_err(|_| FenError::InvalidClock(notation_part.to_string()))
+}
 
 fn piece_to_fen(piece: &Piece) -> char {
     match (piece.color, piece.kind) {
@@ -142,8 +268,8 @@ fn piece_to_fen(piece: &Piece) -> char {
     }    
 }
 
-fn fen_to_piece(notation: char, pos: Position) -> Piece {
-    match notation {
+fn fen_to_piece(notation: char, pos: Position) -> Result<Piece, FenError> {
+    let piece = match notation {
         'r' => Piece{pos, color:Color::Black, kind:PieceKind::Rook},
         'n' => Piece{pos, color:Color::Black, kind:PieceKind::Knight},
         'b' => Piece{pos, color:Color::Black, kind:PieceKind::Bishop},
@@ -159,6 +285,107 @@ fn fen_to_piece(notation: char, pos: Position) -> Piece {
         'P' => Piece{pos, color:Color::White, kind:PieceKind::Pawn},
 
         '*' => Piece{pos, color:Color::Yellow, kind:PieceKind::Duck},
-        _ => panic!("Unkown fen piece"),
+        _ => return Err(FenError::InvalidPiece(notation)),
+    };
+    Ok(piece)
+}
+
+impl fmt::Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FenError::MissingField(field) => write!(f, "the {} field is missing", field),
+            FenError::InvalidPiece(c) => write!(f, "'{}' is not a valid piece", c),
+            FenError::InvalidRankLength(rank) => write!(f, "rank {} does not have 8 squares", rank),
+            FenError::TooManyRanks => write!(f, "there are more than 8 ranks"),
+            FenError::TooFewRanks => write!(f, "there are less than 8 ranks"),
+            FenError::MissingKing(color) => write!(f, "the {:?} king is missing", color),
+            FenError::MultipleDucks => write!(f, "there can be only one duck"),
+            FenError::InvalidColor(color) => write!(f, "'{}' is not a valid color to move, use w or b", color),
+            FenError::InvalidCastle(castle) => write!(f, "'{}' are not valid castling rights", castle),
+            FenError::InvalidEnPassant(square) => write!(f, "'{}' is not a valid en passant square", square),
+            FenError::InvalidClock(clock) => write!(f, "'{}' is not a valid move counter", clock),
+        }
     }
-}
\ No newline at end of file
+}
+
+impl std::error::Error for FenError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::movements::Movement;
+
+    #[test]
+    fn test_fen_errors() {
+        let error = |notation: &str| fen_to_board(notation).err();
+
+        assert_eq!(error(""), Some(FenError::MissingField("pieces")));
+        assert_eq!(error("4k3/8/8/8/8/8/8/4K3"), Some(FenError::MissingField("active color")));
+        assert_eq!(error("4k3/8/8/8/8/8/8/4X3 w"), Some(FenError::InvalidPiece('X')));
+        assert_eq!(error("4k3/8/8/8/8/9/8/4K3 w"), Some(FenError::InvalidPiece('9')));
+        assert_eq!(error("4k3/8/8/8/8/7/8/4K3 w"), Some(FenError::InvalidRankLength(3)));
+        assert_eq!(error("4k3/8/8/8/8/8/8/4K3p w"), Some(FenError::InvalidRankLength(1)));
+        assert_eq!(error("4k3/8/8/8/8/8/8/4K3/8 w"), Some(FenError::TooManyRanks));
+        assert_eq!(error("4k3/8/8/8/8/8/4K3 w"), Some(FenError::TooFewRanks));
+        assert_eq!(error("8/8/8/8/8/8/8/4K3 w"), Some(FenError::MissingKing(Color::Black)));
+        assert_eq!(error("4k3/8/8/3**3/8/8/8/4K3 w"), Some(FenError::MultipleDucks));
+        assert_eq!(error("4k3/8/8/8/8/8/8/4K3 x"), Some(FenError::InvalidColor(String::from("x"))));
+        assert_eq!(error("4k3/8/8/8/8/8/8/4K3 w KX"), Some(FenError::InvalidCastle(String::from("KX"))));
+        assert_eq!(error("4k3/8/8/8/8/8/8/4K3 w - e4"), Some(FenError::InvalidEnPassant(String::from("e4"))));
+        assert_eq!(error("4k3/8/8/8/8/8/8/4K3 w - e3x"), Some(FenError::InvalidEnPassant(String::from("e3x"))));
+        assert_eq!(error("4k3/8/8/8/8/8/8/4K3 w - e3"), Some(FenError::InvalidEnPassant(String::from("e3"))));
+        assert_eq!(error("4k3/8/8/8/8/8/8/4K3 b - e6"), Some(FenError::InvalidEnPassant(String::from("e6"))));
+        assert_eq!(error("4k3/8/8/8/8/8/8/4K3 w - - x 1"), Some(FenError::InvalidClock(String::from("x"))));
+        assert!(fen_to_board("4k3/8/8/3*4/8/8/8/4K3 b").is_ok());
+    }
+
+    #[test]
+    fn test_fen_round_trip() {
+        let notations = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnbqkbnr/pppppppp/8/8/4P3/4*3/PPPP1PPP/RNBQKBNR b KQkq e3 0 1",
+            "rnbqkbnr/ppp1pppp/8/3pP3/8/2*5/PPPP1PPP/RNBQKBNR w KQkq d6 0 3",
+            "r3k2r/8/8/8/8/8/8/R3K2R b Kq - 17 42",
+            "r3kb1r/pp1bpp1p/2np2p1/2pN4/4P3/3PBB1P/PqPQ1PP1/R*2K2R w KQkq - 3 14",
+            "4k3/8/8/8/8/8/8/4K3 w - - 99 120",
+        ];
+
+        for notation in notations {
+            assert_eq!(board_to_fen(&fen_to_board(notation).unwrap()), notation);
+        }
+    }
+
+    #[test]
+    fn test_fen_defaults_and_extensions() {
+        let board = fen_to_board("8/3*4/8/8/8/4K3/8/7k w - - 0 1 q").unwrap();
+        assert_eq!(board_to_fen(&board), "8/3*4/8/8/8/4K3/8/7k w - - 0 1");
+
+        let board = fen_to_board("4k3/8/8/8/8/8/8/4K3 b").unwrap();
+        assert_eq!(board_to_fen(&board), "4k3/8/8/8/8/8/8/4K3 b - - 0 1");
+
+        let board = fen_to_board("r3k2r/8/8/8/8/8/8/R3K2R w HAh - 0 1").unwrap();
+        assert_eq!(board_to_fen(&board), "r3k2r/8/8/8/8/8/8/R3K2R w KQk - 0 1");
+    }
+
+    #[test]
+    fn test_fen_counters_follow_the_game() {
+        let mut board = fen_to_board("4k3/8/8/8/8/8/4P3/4K3 w - - 5 10").unwrap();
+
+        let king = Movement::try_movement(&board, Position(4, 0), Position(3, 0), Position(0, 0), None).unwrap();
+        board.make_movement(king);
+        assert_eq!(board_to_fen(&board), "4k3/8/8/8/8/8/4P3/*2K4 b - - 6 10");
+
+        let reply = Movement::try_movement(&board, Position(4, 7), Position(3, 7), Position(0, 1), None).unwrap();
+        board.make_movement(reply);
+        assert_eq!(board_to_fen(&board), "3k4/8/8/8/8/8/*3P3/3K4 w - - 7 11");
+
+        let pawn = Movement::try_movement(&board, Position(4, 1), Position(4, 3), Position(4, 2), None).unwrap();
+        board.make_movement(pawn);
+        assert_eq!(board_to_fen(&board), "3k4/8/8/8/4P3/4*3/8/3K4 b - e3 0 11");
+
+        board.unmake_movement(pawn);
+        board.unmake_movement(reply);
+        board.unmake_movement(king);
+        assert_eq!(board_to_fen(&board), "4k3/8/8/8/8/8/4P3/4K3 w - - 5 10");
+    }
+}
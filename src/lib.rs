@@ -0,0 +1,46 @@
+//! Deep Duck is an engine for duck chess, the variant where both players share a duck
+//! that must be moved to an empty square after every movement and blocks whatever is behind it.
+//! There is no check: the game ends when a king is captured, and a player without movements wins.
+//!
+//! The library exposes everything the engine itself uses:
+//!
+//! - [`Board`] holds a position, created with [`Board::arranged`] or [`Board::from_fen`]
+//!   and written back with [`Board::to_fen`]. Ducks are written as a `*` in the FEN.
+//! - [`Movement`] is a piece movement together with its duck placement.
+//!   [`Movement::avaliable_moves`] generates them, [`Movement::try_movement`] and
+//!   [`Movement::from_str`] validate them, and [`Board::make_movement`] plays them.
+//!   [`Movement::to_san`] and [`Movement::from_san`] use algebraic notation, like `Nxe5@d4`.
+//! - [`evaluate_limited`] searches a position within some [`SearchLimits`], sharing a
+//!   [`ZobristCache`] between searches, and [`count_centipawns`] is the static evaluation.
+//!
+//! ```
+//! use deep_duck::{evaluate_limited, Board, Movement, SearchLimits, SearchSettings, ZobristCache};
+//!
+//! let mut board = Board::arranged();
+//! let movement = Movement::from_str(&board, "e2e4,d5").unwrap();
+//! board.make_movement(movement);
+//!
+//! let cache = ZobristCache::new();
+//! let evaluation = evaluate_limited(&board, SearchLimits::depth(2), &cache, &SearchSettings::default());
+//! assert!(evaluation.movement.is_some());
+//! ```
+
+pub mod board;
+pub mod pieces;
+pub mod movements;
+pub mod engine;
+pub mod fen;
+pub mod san;
+pub mod evaluation;
+pub mod cache;
+mod ordering;
+mod zobrist;
+
+pub use crate::board::{Board, GameResult};
+pub use crate::cache::ZobristCache;
+pub use crate::engine::{evaluate, evaluate_limited, search, BackgroundSearch, DuckSearch, Evaluation, SearchLimits, SearchSettings};
+pub use crate::evaluation::count_centipawns;
+pub use crate::fen::FenError;
+pub use crate::movements::Movement;
+pub use crate::san::SanError;
+pub use crate::pieces::{Color, Piece, PieceKind, Position};
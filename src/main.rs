@@ -1,20 +1,27 @@
-mod board;
-mod pieces;
-mod movements;
-mod engine;
-mod fen;
-mod evaluation;
-mod cache;
 mod cli;
+mod args;
+mod uci;
+mod xboard;
 
-use crate::cli::{App, Command};
-use std::io::{stdin,stdout,Write};
+use crate::args::{Arguments, Protocol, EXIT_USAGE, USAGE};
+use crate::cli::{run_once, App, Command, Event};
+use crate::uci::{Uci, UciCommand};
+use crate::xboard::XBoard;
+use deep_duck::ZobristCache;
+use std::env;
+use std::io::{stdin,stdout,IsTerminal,Write};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
 
-fn get_input() -> String {
+fn get_input() -> Option<String> {
     let mut input = String::new();
 
-    let _ = stdout().flush();
-    stdin().read_line(&mut input).expect("Did not enter a correct string");
+    match stdin().read_line(&mut input) {
+        Ok(0) | Err(_) => return None,
+        Ok(_) => (),
+    }
 
     if let Some('\n')=input.chars().next_back() {
         input.pop();
@@ -24,29 +31,106 @@ fn get_input() -> String {
         input.pop();
     }
 
-    input
+    Some(input)
+}
+
+// Programs talking to the engine do not need a prompt
+fn prompt() {
+    if !stdin().is_terminal() {
+        return;
+    }
+    print!(">> ");
+    let _ = stdout().flush();
 }
 
-// fn test() {
-//     let start = Instant::now();
+// The prompt loop, until the user exits or asks for one of the protocols
+fn repl(app: &mut App, receiver: &Receiver<Event>) -> Option<Protocol> {
+    let mut closing = false;
+    prompt();
+
+    for event in receiver {
+        match event {
+            Event::Input(input) => match Command::from_str(input) {
+                Command::Exit => {
+                    app.close();
+                    closing = true;
+                },
+                Command::Uci if !app.is_searching() => return Some(Protocol::Uci),
+                Command::XBoard if !app.is_searching() => return Some(Protocol::XBoard),
+                Command::Empty => (),
+                command => app.run(command),
+            },
+            Event::SearchDone(evaluation) => app.finish_search(evaluation),
+            Event::EndOfInput => {
+                app.close();
+                closing = true;
+            },
+        }
 
-//     let board = Board::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
-//     search(&board, 6);
+        // after exiting, or without more commands to read, the engine only waits for the last search
+        if closing && !app.is_searching() {
+            break;
+        }
 
-//     let duration = start.elapsed();
-//     println!("Time elapsed: {:?}", duration);
-// }
+        prompt();
+    }
+
+    None
+}
 
-fn main() {
-    let mut app = App::new();
+fn main() -> ExitCode {
+    let arguments = match Arguments::parse(env::args().skip(1)) {
+        Ok(arguments) => arguments,
+        Err(error) => {
+            eprintln!("deep-duck: {}", error);
+            eprintln!("Type deep-duck --help for more info.");
+            return ExitCode::from(EXIT_USAGE);
+        },
+    };
 
-    loop {
-        print!(">> ");
-        let input = get_input();
-        match Command::from_str(input) {
-            Command::Exit => break,
-            Command::Empty => (),
-            command => app.run(command),
-        };
+    if arguments.help {
+        println!("{}", USAGE);
+        return ExitCode::SUCCESS;
     }
+
+    if arguments.one_shot() {
+        return run_once(&arguments);
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    let input = sender.clone();
+
+    // The input is read in another thread, so the user can still type while the engine thinks
+    thread::spawn(move || {
+        while let Some(line) = get_input() {
+            if input.send(Event::Input(line)).is_err() {
+                return;
+            }
+        }
+        let _ = input.send(Event::EndOfInput);
+    });
+
+    let settings = arguments.settings();
+    let cache = Arc::new(ZobristCache::with_size(arguments.hash));
+
+    match arguments.protocol {
+        Protocol::Repl => {
+            let mut app = App::new(sender.clone(), arguments.board.clone(), arguments.limits(), settings, Arc::clone(&cache));
+
+            match repl(&mut app, &receiver) {
+                // the command that asked for the protocol was already read
+                Some(Protocol::Uci) => {
+                    let mut uci = Uci::new(sender, settings, cache);
+                    uci.run(UciCommand::Uci);
+                    uci.listen(&receiver);
+                },
+                Some(Protocol::XBoard) => XBoard::new(sender, settings, cache).listen(&receiver),
+                _ => (),
+            }
+        },
+        Protocol::Uci => Uci::new(sender, settings, cache).listen(&receiver),
+        Protocol::XBoard => XBoard::new(sender, settings, cache).listen(&receiver),
+    }
+
+    ExitCode::SUCCESS
 }
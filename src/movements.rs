@@ -1,10 +1,15 @@
-use crate::board::Board;
+use crate::board::{Board, Castle};
 use crate::pieces::PieceKind;
 use crate::pieces::Position;
 use crate::pieces::Piece;
 use crate::pieces::Color;
+use crate::san;
+use crate::san::SanError;
+use std::fmt;
 
-
+/// A piece movement followed by the duck placement, as every turn of duck chess.
+/// It also keeps what is needed to take it back, like the captured piece.
+/// Written as "e2e4,d5", or "e7e8q,d5" for promotions.
 #[derive(Copy, Clone, Debug)]
 pub struct Movement {
     pub origin: Position,
@@ -14,7 +19,10 @@ pub struct Movement {
     pub color: Color,
     pub moved: PieceKind,
     pub captured: Option<Piece>,
-    pub promotion: Option<PieceKind>
+    pub promotion: Option<PieceKind>,
+    pub castle_origin: (Castle, Castle),
+    pub en_passant_origin: Option<Position>,
+    pub halfmove_origin: usize,
 }
 
 enum MovementDirection {
@@ -65,42 +73,168 @@ impl Movement {
             moved: origin_piece.kind,
             captured: target_square,
             promotion: None,
+            castle_origin: (board.white_castle, board.black_castle),
+            en_passant_origin: board.en_passant,
+            halfmove_origin: board.halfmove_clock,
         };
 
         Some(movement)
     }
 
-    pub fn try_movement(board: &Board, origin: Position, target: Position, duck: Position) -> Option<Self> {
-        let origin_piece = match board.get_square(origin) {
-            Some(piece) => piece,
-            None => return None,
-        };
-        
-        if (duck != origin) && board.get_square(duck).is_some() {
+    /// When a pawn reaches the last rank without a promotion piece it becomes a queen
+    pub fn try_movement(board: &Board, origin: Position, target: Position, duck: Position, promotion: Option<PieceKind>) -> Option<Self> {
+        let origin_piece = board.get_square(origin)?;
+
+        if origin_piece.color != board.active_color {
             return None;
         }
 
-        if duck == target {
+        for movement in Movement::piece_moves(board, origin) {
+            let promoted = match (movement.promotion, promotion) {
+                (Some(_), None) => movement.promotion == Some(PieceKind::Queen),
+                (promoted, promotion) => promoted == promotion,
+            };
+
+            if movement.origin == origin && movement.target == target && promoted {
+                if !movement.duck_is_free(board, duck) {
+                    return None;
+                }
+                return Some(Movement { duck_target: duck, ..movement });
+            }
+        }
+        
+        None
+    }
+
+    /// Reads the same notation movements are written with, like "e2e4,d5" or "e7e8q,d5".
+    /// Fairy-Stockfish may also write where the duck came from, as in "e2e4,e4d5".
+    pub fn from_str(board: &Board, input: &str) -> Option<Self> {
+        let (piece, duck) = input.trim().split_once(',')?;
+
+        if !piece.is_ascii() || !duck.is_ascii() {
             return None;
         }
 
-        if origin_piece.color != board.active_color {
+        let promotion = match piece.len() {
+            4 => None,
+            5 => match &piece[4..] {
+                "q" | "Q" => Some(PieceKind::Queen),
+                "r" | "R" => Some(PieceKind::Rook),
+                "n" | "N" => Some(PieceKind::Knight),
+                "b" | "B" => Some(PieceKind::Bishop),
+                _ => return None,
+            },
+            _ => return None,
+        };
+
+        let duck = match duck.len() {
+            4 => Position::from_str(&duck[2..])?,
+            _ => Position::from_str(duck)?,
+        };
+
+        Movement::try_movement(board, Position::from_str(&piece[..2])?, Position::from_str(&piece[2..4])?, duck, promotion)
+    }
+
+    /// Writes the movement in algebraic notation, like "Nxe5@d4".
+    /// The board is the position before the movement is played.
+    pub fn to_san(&self, board: &Board) -> String {
+        san::movement_to_san(board, self)
+    }
+
+    /// Reads a movement in algebraic notation, like "Nxe5@d4" or "Nxe5,d4"
+    pub fn from_san(board: &Board, notation: &str) -> Result<Self, SanError> {
+        san::san_to_movement(board, notation)
+    }
+
+    /// Rooks and bishops are never better than a queen, except in very rare positions
+    pub fn is_underpromotion(&self) -> bool {
+        matches!(self.promotion, Some(PieceKind::Rook) | Some(PieceKind::Bishop))
+    }
+
+    pub fn is_quiet(&self) -> bool {
+        self.captured.is_none() && self.promotion.is_none()
+    }
+
+    /// The moved piece attacks the enemy king, which must be saved right away
+    pub fn threatens_king(&self, board: &Board) -> bool {
+        let tmp_board = board.copy_movement(*self);
+
+        Movement::piece_moves(&tmp_board, self.target)
+            .iter()
+            .any(|x| matches!(x.captured, Some(piece) if piece.kind == PieceKind::King))
+    }
+
+    pub fn is_castle(&self) -> bool {
+        self.moved == PieceKind::King && (self.target.0 - self.origin.0).abs() == 2
+    }
+
+    /// The rook jumps over the king to the square it just crossed
+    pub fn castle_rook(&self) -> Option<(Position, Position)> {
+        if !self.is_castle() {
             return None;
         }
 
-        for movement in Movement::piece_moves(board, origin) {
-            if movement.origin == origin && movement.target == target {
-                let (x0, y0, x1, y1) = (origin.0, origin.1, target.0, target.1);
-                if let Some(mut movement) = Movement::from_coords(board, x0, y0, x1, y1) {
-                    movement.duck_target = duck;
-                    return Some(movement);
+        let rank = self.origin.1;
+
+        if self.target.0 > self.origin.0 {
+            Some((Position(7, rank), Position(5, rank)))
+        } else {
+            Some((Position(0, rank), Position(3, rank)))
+        }
+    }
+
+    /// The square skipped by a pawn double move, which the enemy may capture en passant
+    pub fn en_passant_target(&self) -> Option<Position> {
+        if self.moved == PieceKind::Pawn && (self.target.1 - self.origin.1).abs() == 2 {
+            Some(Position(self.origin.0, (self.origin.1 + self.target.1) / 2))
+        } else {
+            None
+        }
+    }
+
+    /// Checks if the duck can land in a square once this movement is done on the board.
+    /// The duck must always change squares, except on the very first move when it enters the board.
+    pub fn duck_is_free(&self, board: &Board, pos: Position) -> bool {
+        if pos == self.target || Some(pos) == board.duck {
+            return false;
+        }
+
+        if let Some((rook_origin, rook_target)) = self.castle_rook() {
+            if pos == rook_target {
+                return false;
+            }
+            if pos == rook_origin {
+                return true;
+            }
+        }
+
+        if let Some(captured) = self.captured {
+            if pos == captured.pos {
+                return true;
+            }
+        }
+
+        pos == self.origin || board.get_square(pos).is_none()
+    }
+
+    /// Every possible duck placement for a piece movement
+    pub fn duck_moves(board: &Board, movement: &Movement) -> Vec<Self> {
+        let mut movements = Vec::<Self>::with_capacity(64);
+
+        for y in 0..8 {
+            for x in 0..8 {
+                let duck_target = Position(x, y);
+                if movement.duck_is_free(board, duck_target) {
+                    movements.push(Movement { duck_target, ..*movement });
                 }
             }
         }
-        
-        None
+
+        movements
     }
 
+    /// Every piece movement of the side to move, each with a single duck placement.
+    /// Use [`Movement::duck_moves`] to get the other placements.
     pub fn avaliable_moves(board: &Board) -> Vec::<Self>{
         let mut movements = Vec::<Self>::with_capacity(140);
         let mut king_found = false;
@@ -123,6 +257,7 @@ impl Movement {
         movements
     }
 
+    /// The movements of a single piece, each with a single duck placement
     pub fn piece_moves(board: &Board, origin: Position) -> Vec::<Self> {
         let piece = board.get_square(origin);
 
@@ -178,7 +313,51 @@ impl Movement {
             (-1, 0), (1, 0), 
             (-1, -1), (0, -1), (1, -1), 
         ]);
-        Self::jump_movements(board, origin, displacement)
+        let mut movements = Self::jump_movements(board, origin, displacement);
+        movements.append(&mut Self::castle_moves(board, origin));
+        movements
+    }
+
+    // There are no checks in duck chess, so the king may castle out of, through or into
+    // an attacked square. But it can not castle through the duck, or any other piece.
+    fn castle_moves(board: &Board, origin: Position) -> Vec<Self> {
+        let mut movements = Vec::<Movement>::new();
+
+        let color = match board.get_square(origin) {
+            Some(piece) => piece.color,
+            None => return movements,
+        };
+
+        let rank = match color {
+            Color::White => 0,
+            Color::Black => 7,
+            Color::Yellow => return movements,
+        };
+
+        if origin != Position(4, rank) {
+            return movements;
+        }
+
+        let castle = board.castle(color);
+        let is_empty = |x: i32| board.get_square(Position(x, rank)).is_none();
+        let is_rook = |x: i32| matches!(
+            board.get_square(Position(x, rank)),
+            Some(Piece{pos:_, color:rook_color, kind:PieceKind::Rook}) if rook_color == color
+        );
+
+        if castle.short && is_rook(7) && is_empty(5) && is_empty(6) {
+            if let Some(movement) = Self::from_coords(board, 4, rank, 6, rank) {
+                movements.push(movement);
+            }
+        }
+
+        if castle.long && is_rook(0) && is_empty(1) && is_empty(2) && is_empty(3) {
+            if let Some(movement) = Self::from_coords(board, 4, rank, 2, rank) {
+                movements.push(movement);
+            }
+        }
+
+        movements
     }
 
     fn knight_moves(board: &Board, origin: Position) -> Vec<Self> {
@@ -236,6 +415,8 @@ impl Movement {
             }
         }
 
+        movements.append(&mut Self::en_passant_moves(board, origin, direction));
+
         // first double move
         if y == promotion - 6*direction {
             let try_movement = Self::from_coords(board, x, y, x, y + direction);
@@ -253,10 +434,42 @@ impl Movement {
         movements
     }
 
+    // If the enemy left the duck on the skipped square en passant is blocked,
+    // from_coords already refuses to move a piece onto the duck.
+    fn en_passant_moves(board: &Board, origin: Position, direction: i32) -> Vec<Self> {
+        let mut movements = Vec::<Movement>::new();
+
+        let target = match board.en_passant {
+            Some(target) => target,
+            None => return movements,
+        };
+
+        let Position(x, y) = origin;
+
+        if (target.0 - x).abs() != 1 || target.1 != y + direction {
+            return movements;
+        }
+
+        let victim = board.get_square(Position(target.0, y));
+        let enemy = if direction > 0 { Color::Black } else { Color::White };
+
+        if let Some(Piece{pos:_, color, kind:PieceKind::Pawn}) = victim {
+            if color == enemy {
+                if let Some(mut movement) = Self::from_coords(board, x, y, target.0, target.1) {
+                    if movement.captured.is_none() {
+                        movement.captured = victim;
+                        movements.push(movement);
+                    }
+                }
+            }
+        }
+
+        movements
+    }
+
     fn promotions(movement: &Movement) -> Vec<Self> {
         let mut movements = Vec::<Movement>::new();
-        let promotions = [PieceKind::Knight, PieceKind::Queen]; // the only reasonable promotions
-        // let promotions = [PieceKind::Rook, PieceKind::Knight, PieceKind::Bishop, PieceKind::Queen];
+        let promotions = [PieceKind::Rook, PieceKind::Knight, PieceKind::Bishop, PieceKind::Queen];
 
         for kind in promotions {
             let mut tmp_movement = *movement;
@@ -318,8 +531,27 @@ impl Movement {
     }
 }
 
+// Written like "e7e8q,d5": the piece movement, the promotion (if any) and the duck square
+impl fmt::Display for Movement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.origin, self.target)?;
+
+        if let Some(promotion) = self.promotion {
+            let letter = match promotion {
+                PieceKind::Queen => "q",
+                PieceKind::Rook => "r",
+                PieceKind::Knight => "n",
+                PieceKind::Bishop => "b",
+                _ => "",
+            };
+            write!(f, "{}", letter)?;
+        }
+
+        write!(f, ",{}", self.duck_target)
+    }
+}
 
-#[allow(dead_code)]
+/// Counts the piece movements until some depth, to check the movement generation
 pub fn perft(board: &mut Board, depth: usize) -> usize {
     if depth == 0 {
         return 1;
@@ -352,14 +584,188 @@ mod tests {
 
     #[test]
     fn test_avaliable_white() {
-        let board = Board::from_fen("k6B/1P6/8/7R/8/1r6/P7/K5QN w - - 0 1");
+        let board = Board::from_fen("k6B/1P6/8/7R/8/1r6/P7/K5QN w - - 0 1").unwrap();
         let avaliable = Movement::avaliable_moves(&board);
-        assert_eq!(avaliable.len(), 48);
+        assert_eq!(avaliable.len(), 52);
+    }
+
+    #[test]
+    fn test_underpromotion() {
+        let mut board = Board::from_fen("k7/2P5/8/8/8/8/8/K7 w - - 0 1").unwrap();
+        let promotions: Vec<Movement> = Movement::avaliable_moves(&board)
+            .into_iter()
+            .filter(|x| x.promotion.is_some())
+            .collect();
+        assert_eq!(promotions.len(), 4);
+        assert_eq!(promotions.iter().filter(|x| x.is_underpromotion()).count(), 2);
+
+        let queen = Movement::try_movement(&board, Position(2, 6), Position(2, 7), Position(4, 4), None).unwrap();
+        assert_eq!(queen.promotion, Some(PieceKind::Queen));
+
+        let rook = Movement::try_movement(&board, Position(2, 6), Position(2, 7), Position(4, 4), Some(PieceKind::Rook)).unwrap();
+        assert_eq!(rook.to_string(), "c7c8r,e5");
+        board.make_movement(rook);
+        assert_eq!(board.get_square(Position(2, 7)).unwrap().kind, PieceKind::Rook);
+
+        board.unmake_movement(rook);
+        assert_eq!(board.get_square(Position(2, 6)).unwrap().kind, PieceKind::Pawn);
+        assert!(board.get_square(Position(2, 7)).is_none());
+
+        assert!(Movement::try_movement(&board, Position(0, 0), Position(1, 1), Position(4, 4), Some(PieceKind::Queen)).is_none());
     }
     
+    #[test]
+    fn test_threatens_king() {
+        let board = Board::from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 0 1").unwrap();
+        let check = Movement::try_movement(&board, Position(0, 0), Position(0, 7), Position(3, 3), None).unwrap();
+        let quiet = Movement::try_movement(&board, Position(0, 0), Position(0, 6), Position(3, 3), None).unwrap();
+
+        assert!(check.threatens_king(&board));
+        assert!(!quiet.threatens_king(&board));
+        assert!(quiet.is_quiet());
+    }
+
+    #[test]
+    fn test_from_str() {
+        let board = Board::from_fen("k7/2P5/8/8/8/8/4P3/K7 w - - 0 1").unwrap();
+
+        let pawn = Movement::from_str(&board, "e2e4,d5").unwrap();
+        assert_eq!((pawn.origin, pawn.target, pawn.duck_target), (Position(4, 1), Position(4, 3), Position(3, 4)));
+        assert_eq!(Movement::from_str(&board, "e2e4,e4d5").unwrap().duck_target, Position(3, 4));
+
+        let rook = Movement::from_str(&board, "c7c8r,e5").unwrap();
+        assert_eq!(rook.promotion, Some(PieceKind::Rook));
+        assert_eq!(Movement::from_str(&board, &rook.to_string()).unwrap().promotion, Some(PieceKind::Rook));
+
+        assert!(Movement::from_str(&board, "e2e4").is_none());
+        assert!(Movement::from_str(&board, "e2e5,d5").is_none());
+        assert!(Movement::from_str(&board, "e2e4,e4").is_none());
+        assert!(Movement::from_str(&board, "e2e4x,d5").is_none());
+        assert!(Movement::from_str(&board, "e2e4,d55").is_none());
+    }
+
+    #[test]
+    fn test_castle_moves() {
+        let board = Board::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+        let castles: Vec<Movement> = Movement::avaliable_moves(&board)
+            .into_iter()
+            .filter(|x| x.is_castle())
+            .collect();
+        assert_eq!(castles.len(), 2);
+
+        let board = Board::from_fen("r3k2r/8/8/8/8/8/8/R*2K2R w Kq - 0 1").unwrap();
+        let castles: Vec<Movement> = Movement::avaliable_moves(&board)
+            .into_iter()
+            .filter(|x| x.is_castle())
+            .collect();
+        assert_eq!(castles.len(), 1);
+        assert_eq!(castles[0].target, Position(6, 0));
+    }
+
+    #[test]
+    fn test_castle_make_unmake() {
+        let mut board = Board::from_fen("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1").unwrap();
+        let movement = Movement::try_movement(&board, Position(4, 7), Position(2, 7), Position(4, 4), None).unwrap();
+
+        board.make_movement(movement);
+        assert_eq!(board.get_square(Position(3, 7)).unwrap().kind, PieceKind::Rook);
+        assert!(board.get_square(Position(0, 7)).is_none());
+        assert_eq!(board.castle(Color::Black), Castle::none());
+
+        board.unmake_movement(movement);
+        assert_eq!(board.get_square(Position(4, 7)).unwrap().kind, PieceKind::King);
+        assert_eq!(board.get_square(Position(0, 7)).unwrap().kind, PieceKind::Rook);
+        assert!(board.get_square(Position(3, 7)).is_none());
+        assert_eq!(board.castle(Color::Black), Castle{short: true, long: true});
+    }
+
+    #[test]
+    fn test_castle_duck_on_rook() {
+        let board = Board::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+        assert!(Movement::try_movement(&board, Position(4, 0), Position(6, 0), Position(5, 0), None).is_none());
+        assert!(Movement::try_movement(&board, Position(4, 0), Position(2, 0), Position(3, 0), None).is_none());
+        assert!(Movement::try_movement(&board, Position(4, 0), Position(2, 0), Position(4, 0), None).is_some());
+    }
+
+    #[test]
+    fn test_castle_revoked_by_capture() {
+        let mut board = Board::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+        let movement = Movement::try_movement(&board, Position(7, 0), Position(7, 7), Position(4, 4), None).unwrap();
+        board.make_movement(movement);
+
+        assert_eq!(board.castle(Color::White), Castle{short: false, long: true});
+        assert_eq!(board.castle(Color::Black), Castle{short: false, long: true});
+    }
+
+    #[test]
+    fn test_en_passant() {
+        let mut board = Board::from_fen("4k3/3p4/8/4P3/8/8/8/4K3 b - - 0 1").unwrap();
+        let movement = Movement::try_movement(&board, Position(3, 6), Position(3, 4), Position(0, 0), None).unwrap();
+        board.make_movement(movement);
+        assert_eq!(board.en_passant, Some(Position(3, 5)));
+        assert_eq!(board.to_fen().split(' ').nth(3), Some("d6"));
+
+        let capture = Movement::try_movement(&board, Position(4, 4), Position(3, 5), Position(0, 1), None).unwrap();
+        assert_eq!(capture.captured.unwrap().pos, Position(3, 4));
+
+        board.make_movement(capture);
+        assert!(board.get_square(Position(3, 4)).is_none());
+        assert_eq!(board.en_passant, None);
+
+        board.unmake_movement(capture);
+        assert_eq!(board.get_square(Position(3, 4)).unwrap().kind, PieceKind::Pawn);
+        assert!(board.get_square(Position(3, 5)).is_none());
+        assert_eq!(board.en_passant, Some(Position(3, 5)));
+    }
+
+    #[test]
+    fn test_en_passant_blocked_by_duck() {
+        let board = Board::from_fen("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1").unwrap();
+        assert!(Movement::try_movement(&board, Position(4, 4), Position(3, 5), Position(0, 0), None).is_some());
+
+        let board = Board::from_fen("4k3/8/3*4/3pP3/8/8/8/4K3 w - d6 0 1").unwrap();
+        assert!(Movement::try_movement(&board, Position(4, 4), Position(3, 5), Position(0, 0), None).is_none());
+    }
+
+    #[test]
+    fn test_duck_moves() {
+        let board = Board::from_fen("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1").unwrap();
+        let movement = Movement::try_movement(&board, Position(4, 1), Position(4, 3), Position(0, 0), None).unwrap();
+        // 64 squares minus the two kings and the pushed pawn
+        assert_eq!(Movement::duck_moves(&board, &movement).len(), 61);
+
+        let board = Board::from_fen("4k3/8/8/8/8/8/8/4K2R w K - 0 1").unwrap();
+        let castle = Movement::try_movement(&board, Position(4, 0), Position(6, 0), Position(0, 0), None).unwrap();
+        let ducks = Movement::duck_moves(&board, &castle);
+        assert!(ducks.iter().any(|x| x.duck_target == Position(7, 0)));
+        assert!(ducks.iter().all(|x| x.duck_target != Position(5, 0)));
+    }
+
+    #[test]
+    fn test_duck_must_move() {
+        let mut board = Board::arranged();
+        assert!(board.duck.is_none());
+
+        // on the first move the duck can go to any empty square, even the one left by the pawn
+        assert!(Movement::try_movement(&board, Position(4, 1), Position(4, 3), Position(4, 1), None).is_some());
+        assert!(Movement::try_movement(&board, Position(4, 1), Position(4, 3), Position(4, 3), None).is_none());
+        assert!(Movement::try_movement(&board, Position(4, 1), Position(4, 3), Position(3, 1), None).is_none());
+
+        let movement = Movement::try_movement(&board, Position(4, 1), Position(4, 3), Position(3, 4), None).unwrap();
+        board.make_movement(movement);
+
+        assert!(Movement::try_movement(&board, Position(4, 6), Position(4, 4), Position(3, 4), None).is_none());
+        assert!(Movement::try_movement(&board, Position(4, 6), Position(4, 4), Position(3, 3), None).is_some());
+
+        for movement in Movement::avaliable_moves(&board) {
+            assert_ne!(Some(movement.duck_target), board.duck);
+            assert!(Movement::duck_moves(&board, &movement).iter().all(|x| Some(x.duck_target) != board.duck));
+        }
+    }
+
     #[test]
     fn test_avaliable_black() {
-        let board = Board::from_fen("4k3/1p6/5r2/2KN4/8/2p5/1PPP4/8 b - - 0 1");
+        let board = Board::from_fen("4k3/1p6/5r2/2KN4/8/2p5/1PPP4/8 b - - 0 1").unwrap();
         let avaliable = Movement::avaliable_moves(&board);
         assert_eq!(avaliable.len(), 23);
     }
@@ -0,0 +1,124 @@
+use crate::movements::Movement;
+use crate::pieces::Color;
+use crate::pieces::PieceKind;
+use crate::pieces::Position;
+
+// Old cutoffs matter less than recent ones, so the tables are halved once they grow too much
+const HISTORY_LIMIT: i32 = 100_000;
+
+// Remembers the movements that caused beta cutoffs during a search.
+// Killers are quiet movements that refuted another position at the same ply,
+// and the history counts how good it was to move a piece to some square
+// (or to put the duck there), no matter where it happened.
+pub struct MoveOrdering {
+    killers: Vec<[Option<Movement>; 2]>,
+    history: [[i32; 64]; 12],
+    duck_history: [[i32; 64]; 2],
+}
+
+impl MoveOrdering {
+    pub fn new(max_ply: usize) -> Self {
+        MoveOrdering {
+            killers: vec![[None; 2]; max_ply + 1],
+            history: [[0; 64]; 12],
+            duck_history: [[0; 64]; 2],
+        }
+    }
+
+    // The slot of a killer movement, the first one being the most recent
+    pub fn killer(&self, ply: usize, movement: &Movement) -> Option<usize> {
+        let killers = self.killers.get(ply)?;
+
+        killers.iter().position(|x| match x {
+            Some(killer) => killer.origin == movement.origin && killer.target == movement.target,
+            None => false,
+        })
+    }
+
+    pub fn history(&self, movement: &Movement) -> i32 {
+        self.history[piece_index(movement.color, movement.moved)][square_index(movement.target)]
+    }
+
+    pub fn duck_history(&self, movement: &Movement) -> i32 {
+        self.duck_history[color_index(movement.color)][square_index(movement.duck_target)]
+    }
+
+    // Deeper cutoffs saved more work, so they are worth more
+    pub fn store_cutoff(&mut self, ply: usize, depth: usize, movement: &Movement) {
+        let bonus = (depth * depth) as i32;
+
+        let duck = &mut self.duck_history[color_index(movement.color)][square_index(movement.duck_target)];
+        *duck += bonus;
+        if *duck > HISTORY_LIMIT {
+            self.duck_history.iter_mut().flatten().for_each(|x| *x /= 2);
+        }
+
+        if !movement.is_quiet() {
+            return;
+        }
+
+        let history = &mut self.history[piece_index(movement.color, movement.moved)][square_index(movement.target)];
+        *history += bonus;
+        if *history > HISTORY_LIMIT {
+            self.history.iter_mut().flatten().for_each(|x| *x /= 2);
+        }
+
+        if let Some(killers) = self.killers.get_mut(ply) {
+            let repeated = killers[0].is_some_and(|x| x.origin == movement.origin && x.target == movement.target);
+
+            if !repeated {
+                killers[1] = killers[0];
+                killers[0] = Some(*movement);
+            }
+        }
+    }
+}
+
+fn square_index(pos: Position) -> usize {
+    (pos.0 + 8 * pos.1) as usize
+}
+
+fn color_index(color: Color) -> usize {
+    match color {
+        Color::White => 0,
+        _ => 1,
+    }
+}
+
+fn piece_index(color: Color, kind: PieceKind) -> usize {
+    let kind_index = match kind {
+        PieceKind::Pawn => 0,
+        PieceKind::Rook => 1,
+        PieceKind::Knight => 2,
+        PieceKind::Bishop => 3,
+        PieceKind::Queen => 4,
+        _ => 5,
+    };
+
+    kind_index + 6 * color_index(color)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Board;
+
+    #[test]
+    fn test_cutoffs() {
+        let board = Board::arranged();
+        let mut ordering = MoveOrdering::new(4);
+
+        let knight = Movement::try_movement(&board, Position(1, 0), Position(2, 2), Position(4, 4), None).unwrap();
+        let pawn = Movement::try_movement(&board, Position(4, 1), Position(4, 3), Position(4, 4), None).unwrap();
+
+        ordering.store_cutoff(2, 3, &knight);
+        ordering.store_cutoff(2, 1, &pawn);
+
+        assert_eq!(ordering.killer(2, &pawn), Some(0));
+        assert_eq!(ordering.killer(2, &knight), Some(1));
+        assert_eq!(ordering.killer(1, &knight), None);
+
+        assert_eq!(ordering.history(&knight), 9);
+        assert_eq!(ordering.duck_history(&pawn), 10);
+    }
+}
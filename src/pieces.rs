@@ -1,6 +1,7 @@
 use std::fmt;
 
 
+/// The duck is the only yellow piece
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Color {
     White,
@@ -26,6 +27,7 @@ pub enum PieceKind {
     Duck,
 }
 
+/// The file and rank of a square, both from 0 to 7
 #[derive(Copy, Clone, PartialEq)]
 pub struct Position(pub i32, pub i32);
 
@@ -62,8 +64,9 @@ impl Piece {
     }
 }
 
-#[allow(dead_code)]
 impl Position {
+    /// Reads a square like "e4", in upper or lower case, and nothing else after it
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(input: &str) -> Option<Self> {
         let mut chars = input.chars();
         let file = chars.next();
@@ -93,6 +96,10 @@ impl Position {
             _ => return None,
         };
 
+        if chars.next().is_some() {
+            return None;
+        }
+
         Some(Position(x, y))
     }
 }
@@ -109,4 +116,11 @@ impl fmt::Debug for Position {
             panic!("Invalid position");
         }
     }
-}
\ No newline at end of file
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let file = (b'a' + self.0 as u8) as char;
+        write!(f, "{}{}", file, self.1 + 1)
+    }
+}
@@ -0,0 +1,267 @@
+use crate::board::Board;
+use crate::pieces::PieceKind;
+use crate::pieces::Position;
+use crate::movements::Movement;
+use std::fmt;
+
+
+/// Why a movement in algebraic notation could not be read
+#[derive(Clone, Debug, PartialEq)]
+pub enum SanError {
+    Empty,
+    MissingDuck(String),
+    InvalidSquare(String),
+    InvalidPromotion(String),
+    NoSuchMovement(String),
+    Ambiguous(String),
+    DuckNotFree(Position),
+}
+
+/// Standard algebraic notation followed by the duck square, like "Nxe5@d4".
+/// Castles are "O-O" and "O-O-O", promotions end with the piece, like "e8=Q@d5",
+/// and pieces are only told apart by file or rank when another one could do the same.
+/// There is no check in duck chess, so there are no check or mate signs either.
+pub fn movement_to_san(board: &Board, movement: &Movement) -> String {
+    let mut san = String::with_capacity(10);
+
+    if let Some((_, rook_target)) = movement.castle_rook() {
+        san.push_str(if rook_target.0 == 5 { "O-O" } else { "O-O-O" });
+    } else {
+        if movement.moved == PieceKind::Pawn {
+            if movement.captured.is_some() {
+                san.push_str(&movement.origin.to_string()[..1]);
+            }
+        } else {
+            san.push(piece_letter(movement.moved));
+            san.push_str(&disambiguation(board, movement));
+        }
+
+        if movement.captured.is_some() {
+            san.push('x');
+        }
+
+        san.push_str(&movement.target.to_string());
+
+        if let Some(promotion) = movement.promotion {
+            san.push('=');
+            san.push(piece_letter(promotion));
+        }
+    }
+
+    san.push('@');
+    san.push_str(&movement.duck_target.to_string());
+    san
+}
+
+/// Reads the notation written by [`movement_to_san`], also accepting the pychess form
+/// with a comma before the duck, like "Nxe5,d4". Capture marks are optional, and so are
+/// the '=' of promotions, the check signs and annotations like '!' or '?'.
+pub fn san_to_movement(board: &Board, notation: &str) -> Result<Movement, SanError> {
+    let notation = notation.trim();
+
+    if notation.is_empty() {
+        return Err(SanError::Empty);
+    }
+
+    let (piece_part, duck_part) = notation
+        .split_once(['@', ','])
+        .ok_or_else(|| SanError::MissingDuck(notation.to_string()))?;
+
+    let piece_part = piece_part.trim_end_matches(['+', '#', '!', '?']);
+    let duck_part = duck_part.trim_end_matches(['+', '#', '!', '?']);
+    let duck = square(duck_part)?;
+
+    let candidates: Vec<Movement> = match piece_part {
+        "O-O" | "0-0" => castles(board, 6),
+        "O-O-O" | "0-0-0" => castles(board, 2),
+        _ => piece_candidates(board, piece_part)?,
+    };
+
+    let movement = match candidates[..] {
+        [movement] => movement,
+        [] => return Err(SanError::NoSuchMovement(piece_part.to_string())),
+        _ => return Err(SanError::Ambiguous(piece_part.to_string())),
+    };
+
+    Movement::try_movement(board, movement.origin, movement.target, duck, movement.promotion)
+        .ok_or(SanError::DuckNotFree(duck))
+}
+
+// The movements that fit something like "Nbxd2", "exd6" or "e8=Q"
+fn piece_candidates(board: &Board, notation: &str) -> Result<Vec<Movement>, SanError> {
+    let mut rest = notation;
+
+    let kind = match rest.chars().next().and_then(letter_piece) {
+        Some(kind) => {
+            rest = &rest[1..];
+            kind
+        },
+        None => PieceKind::Pawn,
+    };
+
+    let promotion = match rest.char_indices().last() {
+        Some((index, letter)) if letter.is_ascii_uppercase() => {
+            let promoted = letter_piece(letter)
+                .filter(|x| !matches!(x, PieceKind::Pawn | PieceKind::King) && kind == PieceKind::Pawn)
+                .ok_or_else(|| SanError::InvalidPromotion(notation.to_string()))?;
+            rest = rest[..index].trim_end_matches('=');
+            Some(promoted)
+        },
+        _ => None,
+    };
+
+    if rest.len() < 2 || !rest.is_ascii() {
+        return Err(SanError::InvalidSquare(rest.to_string()));
+    }
+
+    let target = square(&rest[rest.len() - 2..])?;
+    let hint = rest[..rest.len() - 2].trim_end_matches('x');
+
+    let file = hint.chars().find(|x| ('a'..='h').contains(x)).map(|x| x as i32 - 'a' as i32);
+    let rank = hint.chars().find(|x| ('1'..='8').contains(x)).map(|x| x as i32 - '1' as i32);
+
+    if hint.len() > 2 || hint.len() != file.is_some() as usize + rank.is_some() as usize {
+        return Err(SanError::InvalidSquare(hint.to_string()));
+    }
+
+    // a missing promotion piece is a queen, the same as in the coordinate notation
+    let last_rank = target.1 == 0 || target.1 == 7;
+    let promotion = match promotion {
+        None if kind == PieceKind::Pawn && last_rank => Some(PieceKind::Queen),
+        Some(_) if !last_rank => return Err(SanError::InvalidPromotion(notation.to_string())),
+        promotion => promotion,
+    };
+
+    Ok(Movement::avaliable_moves(board)
+        .into_iter()
+        .filter(|x| x.moved == kind && x.target == target && x.promotion == promotion && !x.is_castle())
+        .filter(|x| file.is_none_or(|file| x.origin.0 == file))
+        .filter(|x| rank.is_none_or(|rank| x.origin.1 == rank))
+        .collect())
+}
+
+// The king movement to the given file, if castling that way is allowed
+fn castles(board: &Board, file: i32) -> Vec<Movement> {
+    Movement::avaliable_moves(board)
+        .into_iter()
+        .filter(|x| x.is_castle() && x.target.0 == file)
+        .collect()
+}
+
+// The file when it is enough to tell the pieces apart, otherwise the rank, otherwise both
+fn disambiguation(board: &Board, movement: &Movement) -> String {
+    let rivals: Vec<Position> = Movement::avaliable_moves(board)
+        .into_iter()
+        .filter(|x| x.moved == movement.moved && x.target == movement.target && x.origin != movement.origin)
+        .map(|x| x.origin)
+        .collect();
+
+    let origin = movement.origin.to_string();
+
+    if rivals.is_empty() {
+        String::new()
+    } else if rivals.iter().all(|x| x.0 != movement.origin.0) {
+        origin[..1].to_string()
+    } else if rivals.iter().all(|x| x.1 != movement.origin.1) {
+        origin[1..].to_string()
+    } else {
+        origin
+    }
+}
+
+fn square(notation: &str) -> Result<Position, SanError> {
+    Position::from_str(notation).ok_or_else(|| SanError::InvalidSquare(notation.to_string()))
+}
+
+fn piece_letter(kind: PieceKind) -> char {
+    match kind {
+        PieceKind::King => 'K',
+        PieceKind::Queen => 'Q',
+        PieceKind::Rook => 'R',
+        PieceKind::Bishop => 'B',
+        PieceKind::Knight => 'N',
+        _ => 'P',
+    }
+}
+
+fn letter_piece(letter: char) -> Option<PieceKind> {
+    match letter {
+        'K' => Some(PieceKind::King),
+        'Q' => Some(PieceKind::Queen),
+        'R' => Some(PieceKind::Rook),
+        'B' => Some(PieceKind::Bishop),
+        'N' => Some(PieceKind::Knight),
+        'P' => Some(PieceKind::Pawn),
+        _ => None,
+    }
+}
+
+impl fmt::Display for SanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SanError::Empty => write!(f, "there is no movement"),
+            SanError::MissingDuck(san) => write!(f, "'{}' does not say where the duck goes, like Nf3@d5", san),
+            SanError::InvalidSquare(square) => write!(f, "'{}' is not a valid square", square),
+            SanError::InvalidPromotion(san) => write!(f, "'{}' is not a valid promotion", san),
+            SanError::NoSuchMovement(san) => write!(f, "'{}' is not a valid movement in this position", san),
+            SanError::Ambiguous(san) => write!(f, "'{}' could be more than one movement, tell the file or rank of the piece", san),
+            SanError::DuckNotFree(square) => write!(f, "the duck can not go to {}", square),
+        }
+    }
+}
+
+impl std::error::Error for SanError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(fen: &str, san: &str) {
+        let board = Board::from_fen(fen).unwrap();
+        let movement = san_to_movement(&board, san).unwrap();
+        assert_eq!(movement_to_san(&board, &movement), san);
+    }
+
+    #[test]
+    fn test_san() {
+        round_trip("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", "e4@d5");
+        round_trip("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", "Nf3@f6");
+        round_trip("4k3/8/8/3p4/4P3/8/8/4K3 w - - 0 1", "exd5@e4");
+        round_trip("4k3/8/8/4p3/8/8/8/1N1NK3 w - - 0 1", "Nbc3@a1");
+        round_trip("4k3/8/8/N7/8/8/8/N3K3 w - - 0 1", "N1b3@a2");
+        round_trip("4k3/8/8/1B6/8/8/8/1B2KB2 w - - 0 1", "Bb1d3@a1");
+        round_trip("k7/2P5/8/8/8/8/8/K7 w - - 0 1", "c8=N@e5");
+        round_trip("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "O-O@e1");
+        round_trip("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1", "O-O-O@e8");
+    }
+
+    #[test]
+    fn test_lenient_san() {
+        let board = Board::from_fen("4k3/8/8/4p3/8/8/8/1N1NK3 w - - 0 1").unwrap();
+        let movement = san_to_movement(&board, "Nb1c3,d4").unwrap();
+        assert_eq!((movement.origin, movement.duck_target), (Position(1, 0), Position(3, 3)));
+
+        let board = Board::from_fen("k7/2P5/8/8/8/8/8/K7 w - - 0 1").unwrap();
+        assert_eq!(san_to_movement(&board, "c8Q+@e5").unwrap().promotion, Some(PieceKind::Queen));
+        assert_eq!(san_to_movement(&board, "c8@e5").unwrap().promotion, Some(PieceKind::Queen));
+
+        let board = Board::from_fen("4k3/8/8/3p4/4P3/8/8/4K3 w - - 0 1").unwrap();
+        assert!(san_to_movement(&board, "ed5!?@e4").is_ok());
+    }
+
+    #[test]
+    fn test_san_errors() {
+        let board = Board::from_fen("4k3/8/8/4p3/8/8/8/1N1NK3 w - - 0 1").unwrap();
+        let error = |san: &str| san_to_movement(&board, san).err();
+
+        assert_eq!(error(" "), Some(SanError::Empty));
+        assert_eq!(error("Nc3"), Some(SanError::MissingDuck(String::from("Nc3"))));
+        assert_eq!(error("Nc3@a9"), Some(SanError::InvalidSquare(String::from("a9"))));
+        assert_eq!(error("Nc9@a1"), Some(SanError::InvalidSquare(String::from("c9"))));
+        assert_eq!(error("Nc3@b1"), Some(SanError::Ambiguous(String::from("Nc3"))));
+        assert_eq!(error("Nc4@a1"), Some(SanError::NoSuchMovement(String::from("Nc4"))));
+        assert_eq!(error("O-O@a1"), Some(SanError::NoSuchMovement(String::from("O-O"))));
+        assert_eq!(error("e4=Q@a1"), Some(SanError::InvalidPromotion(String::from("e4=Q"))));
+        assert_eq!(error("Nbc3@e5"), Some(SanError::DuckNotFree(Position(4, 4))));
+    }
+}
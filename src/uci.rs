@@ -0,0 +1,413 @@
+use deep_duck::board::Board;
+use deep_duck::pieces::Color;
+use deep_duck::movements::Movement;
+use deep_duck::cache::{ZobristCache, DEFAULT_CACHE_SIZE, MAX_CACHE_SIZE};
+use deep_duck::engine::{mate_in, time_for_move, BackgroundSearch, DuckSearch, Evaluation, SearchLimits, SearchSettings, MAX_THREADS, MOVES_TO_GO};
+use crate::cli::Event;
+use std::sync::Arc;
+use std::sync::mpsc::{Receiver, Sender};
+use std::time::Duration;
+
+// The commands a GUI sends, as described by the Universal Chess Interface.
+// Positions without a FEN start from the initial position.
+#[derive(Debug, PartialEq)]
+pub enum UciCommand {
+    Uci,
+    IsReady,
+    NewGame,
+    Position(Option<String>, Vec<String>),
+    Go(GoOptions),
+    Stop,
+    SetOption(String, Option<String>),
+    Quit,
+    Empty,
+    Unknown(String),
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct GoOptions {
+    pub depth: Option<usize>,
+    pub nodes: Option<u64>,
+    pub movetime: Option<Duration>,
+    pub wtime: Option<Duration>,
+    pub btime: Option<Duration>,
+    pub winc: Option<Duration>,
+    pub binc: Option<Duration>,
+    pub movestogo: Option<u32>,
+    pub infinite: bool,
+}
+
+// A search started by "go". Infinite searches keep their result until the GUI asks for it.
+struct UciSearch {
+    background: BackgroundSearch,
+    infinite: bool,
+    stopped: bool,
+    result: Option<Evaluation>,
+}
+
+pub struct Uci {
+    board: Board,
+    cache: Arc<ZobristCache>,
+    settings: SearchSettings,
+    events: Sender<Event>,
+    search: Option<UciSearch>,
+}
+
+impl Uci {
+    pub fn new(events: Sender<Event>, settings: SearchSettings, cache: Arc<ZobristCache>) -> Self {
+        Uci {
+            board: Board::arranged(),
+            cache,
+            settings,
+            events,
+            search: None,
+        }
+    }
+
+    // Reads commands until "quit" or the end of the input, then waits for the last search
+    pub fn listen(&mut self, receiver: &Receiver<Event>) {
+        let mut closing = false;
+
+        for event in receiver {
+            match event {
+                Event::Input(input) => match UciCommand::from_str(&input) {
+                    UciCommand::Quit => {
+                        self.stop_search();
+                        closing = true;
+                    },
+                    command => self.run(command),
+                },
+                Event::SearchDone(evaluation) => self.finish_search(evaluation),
+                Event::EndOfInput => {
+                    self.stop_search();
+                    closing = true;
+                },
+            }
+
+            if closing && !self.is_searching() {
+                break;
+            }
+        }
+    }
+
+    pub fn is_searching(&self) -> bool {
+        self.search.is_some()
+    }
+
+    pub fn run(&mut self, command: UciCommand) {
+        match command {
+            UciCommand::Uci => Uci::identify(),
+            UciCommand::IsReady => println!("readyok"),
+            UciCommand::NewGame => self.new_game(),
+            UciCommand::Position(fen, moves) => self.set_position(fen.as_deref(), &moves),
+            UciCommand::Go(options) => self.start_search(options),
+            UciCommand::Stop => self.stop_search(),
+            UciCommand::SetOption(name, value) => self.set_option(&name, value.as_deref()),
+            UciCommand::Unknown(input) => println!("info string Unknown command: {}", input),
+            UciCommand::Quit | UciCommand::Empty => (),
+        }
+    }
+
+    fn identify() {
+        println!("id name Deep Duck {}", env!("CARGO_PKG_VERSION"));
+        println!("id author andrefpf");
+        println!("option name UCI_Variant type combo default duck var duck");
+        println!("option name Hash type spin default {} min 1 max {}", DEFAULT_CACHE_SIZE, MAX_CACHE_SIZE);
+        println!("option name Clear Hash type button");
+        println!("option name Threads type spin default 1 min 1 max {}", MAX_THREADS);
+        println!("option name Ducks type combo default heuristic var heuristic var full");
+        println!("option name Underpromotions type check default false");
+        println!("uciok");
+    }
+
+    fn new_game(&mut self) {
+        self.board = Board::arranged();
+        self.cache.clear();
+    }
+
+    // The previous position is kept unless every movement is valid
+    fn set_position(&mut self, fen: Option<&str>, moves: &[String]) {
+        let board = match fen {
+            Some(fen) => Board::from_fen(fen),
+            None => Ok(Board::arranged()),
+        };
+
+        let mut board = match board {
+            Ok(board) => board,
+            Err(error) => return println!("info string Invalid FEN: {}", error),
+        };
+
+        for text in moves {
+            match Movement::from_str(&board, text) {
+                Some(movement) => board.make_movement(movement),
+                None => return println!("info string Invalid movement: {}", text),
+            }
+        }
+
+        self.board = board;
+    }
+
+    fn start_search(&mut self, options: GoOptions) {
+        if self.is_searching() {
+            return println!("info string Already searching");
+        }
+
+        let events = self.events.clone();
+        let background = BackgroundSearch::start(
+            self.board.clone(),
+            options.limits(self.board.active_color()),
+            Arc::clone(&self.cache),
+            self.settings,
+            Uci::print_info,
+            move |evaluation| { let _ = events.send(Event::SearchDone(evaluation)); },
+        );
+
+        self.search = Some(UciSearch {
+            background,
+            infinite: options.is_infinite(),
+            stopped: false,
+            result: None,
+        });
+    }
+
+    fn stop_search(&mut self) {
+        let result = match &mut self.search {
+            Some(search) => {
+                search.background.stop();
+                search.stopped = true;
+                search.result.take()
+            },
+            None => None,
+        };
+
+        if let Some(evaluation) = result {
+            self.finish_search(evaluation);
+        }
+    }
+
+    // The best movement can only be sent after a "stop" when the search was infinite
+    pub fn finish_search(&mut self, evaluation: Evaluation) {
+        let search = match self.search.take() {
+            Some(search) => search,
+            None => return,
+        };
+
+        if search.infinite && !search.stopped {
+            self.search = Some(UciSearch { result: Some(evaluation), ..search });
+            return;
+        }
+
+        // there is no movement only when the game is already over
+        match evaluation.movement {
+            Some(movement) => println!("bestmove {}", movement),
+            None => println!("bestmove 0000"),
+        }
+    }
+
+    fn set_option(&mut self, name: &str, value: Option<&str>) {
+        let number = value.and_then(|x| x.parse::<usize>().ok());
+
+        match (name.to_lowercase().as_str(), value) {
+            ("uci_variant", Some("duck")) => (),
+            ("hash", _) if number.is_some() => {
+                let size = number.unwrap().clamp(1, MAX_CACHE_SIZE);
+                self.cache = Arc::new(ZobristCache::with_size(size));
+            },
+            ("clear hash", _) => self.cache.clear(),
+            ("threads", _) if number.is_some() => {
+                self.settings.threads = number.unwrap().clamp(1, MAX_THREADS);
+            },
+            ("ducks", Some("heuristic")) => self.settings.ducks = DuckSearch::Heuristic,
+            ("ducks", Some("full")) => {
+                self.cache.clear();
+                self.settings.ducks = DuckSearch::Full;
+            },
+            ("underpromotions", Some("true")) => self.settings.underpromotions = true,
+            ("underpromotions", Some("false")) => self.settings.underpromotions = false,
+            _ => println!("info string Invalid option: {} {}", name, value.unwrap_or("")),
+        }
+    }
+
+    // Scores are from the point of view of the engine, mates are counted in movements
+    fn print_info(depth: usize, evaluation: &Evaluation) {
+        let score = match mate_in(evaluation.score) {
+            Some(moves) => format!("mate {}", moves),
+            None => format!("cp {}", evaluation.score),
+        };
+
+        let stats = &evaluation.stats;
+        let line: Vec<String> = evaluation.pv.iter().map(|x| x.to_string()).collect();
+        println!("info depth {} seldepth {} score {} nodes {} nps {} time {} pv {}",
+            depth, stats.max_ply, score, stats.total_nodes(), stats.nodes_per_second(),
+            stats.elapsed.as_millis(), line.join(" "));
+    }
+}
+
+impl GoOptions {
+    // Searching without any limit is the same as an infinite search
+    pub fn is_infinite(&self) -> bool {
+        let limited = self.depth.is_some() || self.nodes.is_some() || self.movetime.is_some();
+        let clock = self.wtime.is_some() || self.btime.is_some();
+        self.infinite || !limited && !clock
+    }
+
+    pub fn limits(&self, color: Color) -> SearchLimits {
+        if self.is_infinite() {
+            return SearchLimits::default();
+        }
+
+        let (remaining, increment) = match color {
+            Color::Black => (self.btime, self.binc),
+            _ => (self.wtime, self.winc),
+        };
+
+        let clock = remaining.map(|x| {
+            time_for_move(x, increment.unwrap_or_default(), self.movestogo.unwrap_or(MOVES_TO_GO))
+        });
+
+        let time = match (self.movetime, clock) {
+            (Some(movetime), Some(clock)) => Some(movetime.min(clock)),
+            (movetime, clock) => movetime.or(clock),
+        };
+
+        SearchLimits {
+            depth: self.depth,
+            time,
+            nodes: self.nodes,
+        }
+    }
+}
+
+impl UciCommand {
+    pub fn from_str(input: &str) -> Self {
+        let mut words = input.split_whitespace();
+
+        match words.next() {
+            None => UciCommand::Empty,
+            Some("uci") => UciCommand::Uci,
+            Some("isready") => UciCommand::IsReady,
+            Some("ucinewgame") => UciCommand::NewGame,
+            Some("stop") => UciCommand::Stop,
+            Some("quit") => UciCommand::Quit,
+            Some("position") => UciCommand::parse_position(words.collect()),
+            Some("go") => UciCommand::parse_go(words.collect()),
+            Some("setoption") => UciCommand::parse_option(words.collect()),
+            // nothing to do about debugging, registering or pondering
+            Some("debug") | Some("register") | Some("ponderhit") => UciCommand::Empty,
+            Some(_) => UciCommand::Unknown(input.trim().to_string()),
+        }
+    }
+
+    // position [startpos | fen <fen>] moves <move> <move> ...
+    fn parse_position(words: Vec<&str>) -> Self {
+        let (board, moves) = match words.iter().position(|x| *x == "moves") {
+            Some(index) => (&words[..index], &words[index + 1..]),
+            None => (&words[..], &[][..]),
+        };
+
+        let moves = moves.iter().map(|x| x.to_string()).collect();
+
+        match board {
+            ["startpos"] => UciCommand::Position(None, moves),
+            ["fen", fen @ ..] if !fen.is_empty() => UciCommand::Position(Some(fen.join(" ")), moves),
+            _ => UciCommand::Unknown(format!("position {}", words.join(" "))),
+        }
+    }
+
+    fn parse_go(words: Vec<&str>) -> Self {
+        let mut options = GoOptions::default();
+        let mut words = words.into_iter();
+
+        let millis = |x: Option<&str>| x.and_then(|x| x.parse::<i64>().ok()).map(|x| Duration::from_millis(x.max(0) as u64));
+
+        while let Some(word) = words.next() {
+            match word {
+                "depth" => options.depth = words.next().and_then(|x| x.parse().ok()),
+                "nodes" => options.nodes = words.next().and_then(|x| x.parse().ok()),
+                "movestogo" => options.movestogo = words.next().and_then(|x| x.parse().ok()),
+                "movetime" => options.movetime = millis(words.next()),
+                "wtime" => options.wtime = millis(words.next()),
+                "btime" => options.btime = millis(words.next()),
+                "winc" => options.winc = millis(words.next()),
+                "binc" => options.binc = millis(words.next()),
+                "infinite" => options.infinite = true,
+                _ => (),
+            }
+        }
+
+        UciCommand::Go(options)
+    }
+
+    // setoption name <name> [value <value>], where names may have spaces
+    fn parse_option(words: Vec<&str>) -> Self {
+        let value_index = words.iter().position(|x| *x == "value");
+
+        let name = match (words.first(), value_index) {
+            (Some(&"name"), Some(index)) => words[1..index].join(" "),
+            (Some(&"name"), None) => words[1..].join(" "),
+            _ => return UciCommand::Unknown(format!("setoption {}", words.join(" "))),
+        };
+
+        let value = value_index.map(|index| words[index + 1..].join(" "));
+        UciCommand::SetOption(name, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_position() {
+        let command = UciCommand::from_str("position startpos moves e2e4,d5 e7e5,e4d4");
+        assert_eq!(command, UciCommand::Position(None, vec!["e2e4,d5".to_string(), "e7e5,e4d4".to_string()]));
+
+        let command = UciCommand::from_str("position fen 4k3/8/8/8/8/8/8/4K3 w - - 0 1");
+        assert_eq!(command, UciCommand::Position(Some("4k3/8/8/8/8/8/8/4K3 w - - 0 1".to_string()), vec![]));
+
+        let (sender, _receiver) = std::sync::mpsc::channel();
+        let mut uci = Uci::new(sender, SearchSettings::default(), Arc::new(ZobristCache::new()));
+        uci.run(UciCommand::from_str("position startpos moves e2e4,d5 e7e5,d4"));
+        assert_eq!(uci.board.to_fen(), "rnbqkbnr/pppp1ppp/8/4p3/3*P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2");
+
+        // a position with an invalid movement is ignored as a whole
+        uci.run(UciCommand::from_str("position startpos moves e2e4,d5 e7e5,d5 d2d4,d3"));
+        assert_eq!(uci.board.to_fen(), "rnbqkbnr/pppp1ppp/8/4p3/3*P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2");
+    }
+
+    #[test]
+    fn test_go() {
+        let command = UciCommand::from_str("go wtime 60000 btime 30000 winc 1000 binc 0");
+        let options = match command {
+            UciCommand::Go(options) => options,
+            _ => panic!("Not a go command."),
+        };
+
+        assert!(!options.is_infinite());
+        assert_eq!(options.limits(Color::White).time, Some(Duration::from_millis(2750)));
+        assert_eq!(options.limits(Color::Black).time, Some(Duration::from_millis(1000)));
+
+        // running out of time, the engine still leaves some of it for the GUI
+        let options = GoOptions { wtime: Some(Duration::from_millis(60)), movestogo: Some(1), ..GoOptions::default() };
+        assert_eq!(options.limits(Color::White).time, Some(Duration::from_millis(10)));
+
+        assert_eq!(UciCommand::from_str("go depth 5"), UciCommand::Go(GoOptions { depth: Some(5), ..GoOptions::default() }));
+        assert!(GoOptions::default().is_infinite());
+    }
+
+    #[test]
+    fn test_setoption() {
+        let command = UciCommand::from_str("setoption name Clear Hash");
+        assert_eq!(command, UciCommand::SetOption("Clear Hash".to_string(), None));
+
+        let command = UciCommand::from_str("setoption name UCI_Variant value duck");
+        assert_eq!(command, UciCommand::SetOption("UCI_Variant".to_string(), Some("duck".to_string())));
+
+        let (sender, _receiver) = std::sync::mpsc::channel();
+        let mut uci = Uci::new(sender, SearchSettings::default(), Arc::new(ZobristCache::new()));
+        uci.run(UciCommand::from_str("setoption name Threads value 4"));
+        uci.run(UciCommand::from_str("setoption name Hash value 1"));
+        assert_eq!(uci.settings.threads, 4);
+        assert_eq!(uci.cache.size(), 1);
+    }
+}
@@ -0,0 +1,415 @@
+use deep_duck::board::Board;
+use deep_duck::pieces::Color;
+use deep_duck::movements::Movement;
+use deep_duck::cache::{ZobristCache, MAX_CACHE_SIZE};
+use deep_duck::engine::{mate_in, time_for_move, BackgroundSearch, Evaluation, SearchLimits, SearchSettings, MAX_THREADS, MOVES_TO_GO};
+use crate::cli::Event;
+use std::sync::Arc;
+use std::sync::mpsc::{Receiver, Sender};
+use std::time::Duration;
+
+// Used until the GUI sends a time control
+const DEFAULT_MOVE_TIME: Duration = Duration::from_secs(5);
+// Mates are shown as this plus the moves until the king capture
+const MATE_SCORE: i32 = 100_000;
+
+// The commands of the Chess Engine Communication Protocol, used by XBoard and WinBoard.
+// Times are sent in centiseconds and levels as "moves minutes[:seconds] increment".
+#[derive(Debug, PartialEq)]
+pub enum XBoardCommand {
+    XBoard,
+    ProtoVer(u32),
+    New,
+    Variant(String),
+    SetBoard(String),
+    UserMove(String),
+    Go,
+    PlayOther,
+    Force,
+    MoveNow,
+    Undo,
+    Remove,
+    Level(u32, Duration, Duration),
+    FixedTime(Duration),
+    Depth(usize),
+    Time(Duration),
+    Post(bool),
+    Result(String),
+    Ping(String),
+    Memory(usize),
+    Cores(usize),
+    Quit,
+    Empty,
+    Unknown(String),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum TimeControl {
+    // the same time for every movement
+    Fixed(Duration),
+    // some movements in the time left, zero moves means the whole game
+    Clock { moves: u32, increment: Duration },
+}
+
+pub struct XBoard {
+    board: Board,
+    history: Vec<Movement>,
+    cache: Arc<ZobristCache>,
+    settings: SearchSettings,
+    events: Sender<Event>,
+    // searches that are not needed anymore are waited for,
+    // so their result can be thrown away when it arrives
+    search: Option<BackgroundSearch>,
+    discarded: usize,
+    // the side played by the engine, none in force mode
+    engine_color: Option<Color>,
+    post: bool,
+    depth: Option<usize>,
+    time_control: TimeControl,
+    clock: Option<Duration>,
+}
+
+impl XBoard {
+    pub fn new(events: Sender<Event>, settings: SearchSettings, cache: Arc<ZobristCache>) -> Self {
+        XBoard {
+            board: Board::arranged(),
+            history: Vec::new(),
+            cache,
+            settings,
+            events,
+            search: None,
+            discarded: 0,
+            engine_color: Some(Color::Black),
+            post: false,
+            depth: None,
+            time_control: TimeControl::Fixed(DEFAULT_MOVE_TIME),
+            clock: None,
+        }
+    }
+
+    // Reads commands until "quit" or the end of the input, a search still running is cancelled
+    pub fn listen(&mut self, receiver: &Receiver<Event>) {
+        for event in receiver {
+            match event {
+                Event::Input(input) => match XBoardCommand::from_str(&input) {
+                    XBoardCommand::Quit => break,
+                    command => self.run(command),
+                },
+                Event::SearchDone(evaluation) => self.finish_search(evaluation),
+                Event::EndOfInput => break,
+            }
+        }
+
+        self.cancel_search();
+    }
+
+    pub fn is_searching(&self) -> bool {
+        self.search.is_some()
+    }
+
+    pub fn run(&mut self, command: XBoardCommand) {
+        match command {
+            XBoardCommand::ProtoVer(version) if version >= 2 => XBoard::features(),
+            XBoardCommand::New => self.new_game(),
+            XBoardCommand::Variant(variant) => XBoard::change_variant(&variant),
+            XBoardCommand::SetBoard(fen) => self.load_board(&fen),
+            XBoardCommand::UserMove(text) => self.user_move(&text),
+            XBoardCommand::Go => self.play(self.board.active_color()),
+            XBoardCommand::PlayOther => self.play(self.board.active_color().invert()),
+            XBoardCommand::Force => self.force(),
+            XBoardCommand::MoveNow => self.move_now(),
+            XBoardCommand::Undo => self.undo(1),
+            XBoardCommand::Remove => self.undo(2),
+            XBoardCommand::Level(moves, base, increment) => {
+                self.time_control = TimeControl::Clock { moves, increment };
+                self.clock = Some(base);
+            },
+            XBoardCommand::FixedTime(time) => self.time_control = TimeControl::Fixed(time),
+            XBoardCommand::Depth(depth) => self.depth = Some(depth),
+            XBoardCommand::Time(time) => self.clock = Some(time),
+            XBoardCommand::Post(enabled) => self.post = enabled,
+            XBoardCommand::Result(_) => self.force(),
+            XBoardCommand::Ping(number) => println!("pong {}", number),
+            XBoardCommand::Memory(size) => self.cache = Arc::new(ZobristCache::with_size(size.clamp(1, MAX_CACHE_SIZE))),
+            XBoardCommand::Cores(cores) => self.settings.threads = cores.clamp(1, MAX_THREADS),
+            XBoardCommand::Unknown(input) => println!("Error (unknown command): {}", input),
+            XBoardCommand::XBoard | XBoardCommand::ProtoVer(_) | XBoardCommand::Quit | XBoardCommand::Empty => (),
+        }
+    }
+
+    fn features() {
+        println!("feature myname=\"Deep Duck {}\" variants=\"duck\"", env!("CARGO_PKG_VERSION"));
+        println!("feature setboard=1 usermove=1 ping=1 playother=1 san=0 colors=0 sigint=0 sigterm=0 analyze=0 memory=1 smp=1");
+        println!("feature done=1");
+    }
+
+    fn new_game(&mut self) {
+        self.cancel_search();
+        self.board = Board::arranged();
+        self.history.clear();
+        self.cache.clear();
+        self.engine_color = Some(Color::Black);
+        self.depth = None;
+    }
+
+    fn change_variant(variant: &str) {
+        if variant != "duck" {
+            println!("Error (unsupported variant): {}", variant);
+        }
+    }
+
+    fn load_board(&mut self, fen: &str) {
+        self.cancel_search();
+
+        match Board::from_fen(fen) {
+            Ok(board) => {
+                self.board = board;
+                self.history.clear();
+            },
+            Err(error) => println!("tellusererror Illegal position: {}", error),
+        }
+    }
+
+    fn user_move(&mut self, text: &str) {
+        self.cancel_search();
+
+        if self.board.outcome().is_some() {
+            return println!("Illegal move (the game is over): {}", text);
+        }
+
+        match Movement::from_str(&self.board, text) {
+            Some(movement) => {
+                self.make_movement(movement);
+                self.think();
+            },
+            None => println!("Illegal move: {}", text),
+        }
+    }
+
+    fn play(&mut self, color: Color) {
+        self.engine_color = Some(color);
+        self.think();
+    }
+
+    fn force(&mut self) {
+        self.cancel_search();
+        self.engine_color = None;
+    }
+
+    fn undo(&mut self, count: usize) {
+        self.cancel_search();
+
+        for _ in 0..count {
+            if let Some(movement) = self.history.pop() {
+                self.board.unmake_movement(movement);
+            }
+        }
+    }
+
+    fn make_movement(&mut self, movement: Movement) {
+        self.board.make_movement(movement);
+        self.history.push(movement);
+
+        if let Some(result) = self.board.outcome() {
+            println!("{} {{{}}}", result.score(), result);
+        }
+    }
+
+    // Starts thinking when it is the turn of the engine
+    fn think(&mut self) {
+        if self.is_searching() || self.engine_color != Some(self.board.active_color()) || self.board.outcome().is_some() {
+            return;
+        }
+
+        let events = self.events.clone();
+        let post = self.post;
+
+        let report = move |depth, evaluation: &Evaluation| {
+            if post {
+                XBoard::print_thinking(depth, evaluation);
+            }
+        };
+
+        self.search = Some(BackgroundSearch::start(
+            self.board.clone(),
+            self.limits(),
+            Arc::clone(&self.cache),
+            self.settings,
+            report,
+            move |evaluation| { let _ = events.send(Event::SearchDone(evaluation)); },
+        ));
+    }
+
+    fn limits(&self) -> SearchLimits {
+        let time = match self.time_control {
+            TimeControl::Fixed(time) => time,
+            TimeControl::Clock { moves, increment } => {
+                let played = (self.board.fullmove_number() as u32).saturating_sub(1);
+                let moves_to_go = if moves > 0 { moves - played % moves } else { MOVES_TO_GO };
+                let clock = self.clock.unwrap_or(DEFAULT_MOVE_TIME);
+                time_for_move(clock, increment, moves_to_go)
+            },
+        };
+
+        SearchLimits {
+            depth: self.depth,
+            time: Some(time),
+            nodes: None,
+        }
+    }
+
+    fn move_now(&mut self) {
+        if let Some(search) = &self.search {
+            search.stop();
+        }
+    }
+
+    // The result of the search is still sent, so it is counted to be ignored later
+    fn cancel_search(&mut self) {
+        if let Some(search) = self.search.take() {
+            search.stop();
+            search.join();
+            self.discarded += 1;
+        }
+    }
+
+    pub fn finish_search(&mut self, evaluation: Evaluation) {
+        if self.discarded > 0 {
+            self.discarded -= 1;
+            return;
+        }
+
+        if self.search.take().is_none() {
+            return;
+        }
+
+        // the engine only thinks while the game goes on, and the first iteration always finishes
+        let movement = evaluation.movement.expect("a search of an unfinished game has a movement");
+        println!("move {}", movement);
+        self.make_movement(movement);
+    }
+
+    // ply score time nodes pv, with the time in centiseconds
+    fn print_thinking(depth: usize, evaluation: &Evaluation) {
+        let score = match mate_in(evaluation.score) {
+            Some(moves) => moves.signum() * (MATE_SCORE + moves.abs()),
+            None => evaluation.score,
+        };
+
+        let stats = &evaluation.stats;
+        let line: Vec<String> = evaluation.pv.iter().map(|x| x.to_string()).collect();
+        println!("{} {} {} {} {}", depth, score, stats.elapsed.as_millis() / 10, stats.total_nodes(), line.join(" "));
+    }
+}
+
+impl XBoardCommand {
+    pub fn from_str(input: &str) -> Self {
+        let (key, val) = match input.trim().split_once(' ') {
+            Some((key, val)) => (key, val.trim()),
+            None => (input.trim(), ""),
+        };
+
+        let centiseconds = |x: &str| x.parse::<u64>().ok().map(|x| Duration::from_millis(10 * x));
+
+        let command = match key {
+            "" => Some(XBoardCommand::Empty),
+            "xboard" => Some(XBoardCommand::XBoard),
+            "protover" => val.parse().ok().map(XBoardCommand::ProtoVer),
+            "new" => Some(XBoardCommand::New),
+            "variant" => Some(XBoardCommand::Variant(val.to_string())),
+            "setboard" => Some(XBoardCommand::SetBoard(val.to_string())),
+            "usermove" => Some(XBoardCommand::UserMove(val.to_string())),
+            "go" => Some(XBoardCommand::Go),
+            "playother" => Some(XBoardCommand::PlayOther),
+            "force" => Some(XBoardCommand::Force),
+            "?" => Some(XBoardCommand::MoveNow),
+            "undo" => Some(XBoardCommand::Undo),
+            "remove" => Some(XBoardCommand::Remove),
+            "level" => XBoardCommand::parse_level(val),
+            "st" => val.parse::<f64>().ok().filter(|x| *x > 0.0).map(|x| XBoardCommand::FixedTime(Duration::from_secs_f64(x))),
+            "sd" => val.parse().ok().map(XBoardCommand::Depth),
+            "time" => centiseconds(val).map(XBoardCommand::Time),
+            "post" => Some(XBoardCommand::Post(true)),
+            "nopost" => Some(XBoardCommand::Post(false)),
+            "result" => Some(XBoardCommand::Result(val.to_string())),
+            "ping" => Some(XBoardCommand::Ping(val.to_string())),
+            "memory" => val.parse().ok().map(XBoardCommand::Memory),
+            "cores" => val.parse().ok().map(XBoardCommand::Cores),
+            "quit" => Some(XBoardCommand::Quit),
+            // nothing to do about the opponent clock, ratings, pondering and the like
+            "accepted" | "rejected" | "otim" | "hard" | "easy" | "random" | "computer" | "name" | "rating" | "ics"
+                | "white" | "black" | "draw" => Some(XBoardCommand::Empty),
+            _ => None,
+        };
+
+        command.unwrap_or_else(|| XBoardCommand::Unknown(input.trim().to_string()))
+    }
+
+    // level 40 5 0, level 0 2:30 1 or level 0 1 0.5
+    fn parse_level(val: &str) -> Option<Self> {
+        let words: Vec<&str> = val.split_whitespace().collect();
+
+        let [moves, base, increment] = words[..] else {
+            return None;
+        };
+
+        let base = match base.split_once(':') {
+            Some((minutes, seconds)) => 60 * minutes.parse::<u64>().ok()? + seconds.parse::<u64>().ok()?,
+            None => 60 * base.parse::<u64>().ok()?,
+        };
+
+        let increment = increment.parse::<f64>().ok().filter(|x| *x >= 0.0)?;
+        Some(XBoardCommand::Level(moves.parse().ok()?, Duration::from_secs(base), Duration::from_secs_f64(increment)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_level() {
+        let command = XBoardCommand::from_str("level 40 2:30 0");
+        assert_eq!(command, XBoardCommand::Level(40, Duration::from_secs(150), Duration::ZERO));
+
+        let command = XBoardCommand::from_str("level 0 1 0.5");
+        assert_eq!(command, XBoardCommand::Level(0, Duration::from_secs(60), Duration::from_millis(500)));
+
+        assert_eq!(XBoardCommand::from_str("time 1500"), XBoardCommand::Time(Duration::from_secs(15)));
+        assert_eq!(XBoardCommand::from_str("st 2"), XBoardCommand::FixedTime(Duration::from_secs(2)));
+        assert!(matches!(XBoardCommand::from_str("level 40"), XBoardCommand::Unknown(_)));
+    }
+
+    #[test]
+    fn test_moves_and_undo() {
+        let (sender, _receiver) = std::sync::mpsc::channel();
+        let mut xboard = XBoard::new(sender, SearchSettings::default(), Arc::new(ZobristCache::new()));
+
+        xboard.run(XBoardCommand::from_str("force"));
+        xboard.run(XBoardCommand::from_str("usermove e2e4,d5"));
+        xboard.run(XBoardCommand::from_str("usermove e7e5,d5"));
+        xboard.run(XBoardCommand::from_str("usermove e7e5,d4"));
+        assert!(!xboard.is_searching());
+        assert_eq!(xboard.history.len(), 2);
+
+        xboard.run(XBoardCommand::from_str("remove"));
+        assert_eq!(xboard.board.to_fen(), Board::arranged().to_fen());
+
+        // the clock is shared by the movements left until the next time control
+        xboard.run(XBoardCommand::from_str("level 40 5 0"));
+        xboard.run(XBoardCommand::from_str("time 6000"));
+        assert_eq!(xboard.limits().time, Some(Duration::from_millis(1500)));
+    }
+
+    #[test]
+    fn test_resources() {
+        let (sender, _receiver) = std::sync::mpsc::channel();
+        let mut xboard = XBoard::new(sender, SearchSettings::default(), Arc::new(ZobristCache::new()));
+
+        xboard.run(XBoardCommand::from_str("cores 1000"));
+        assert_eq!(xboard.settings.threads, MAX_THREADS);
+
+        xboard.run(XBoardCommand::from_str("memory 0"));
+        assert_eq!(xboard.cache.size(), 1);
+    }
+}
@@ -0,0 +1,110 @@
+use crate::board::Castle;
+use crate::pieces::Piece;
+use crate::pieces::Color;
+use crate::pieces::PieceKind;
+use crate::pieces::Position;
+
+const PIECE_KEYS_SIZE: usize = 64*7*3;
+
+struct ZobristKeys {
+    pieces: [u64; PIECE_KEYS_SIZE],
+    color: u64,
+    castle: [u64; 4],
+    en_passant: [u64; 8],
+}
+
+// The keys are generated at compile time with splitmix64, so every board
+// (and every run of the engine) agrees on the hash of a position.
+static KEYS: ZobristKeys = generate_keys(0x2545_F491_4F6C_DD1D);
+
+const fn splitmix64(state: u64) -> (u64, u64) {
+    let state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    (state, z ^ (z >> 31))
+}
+
+const fn generate_keys(seed: u64) -> ZobristKeys {
+    let mut state = seed;
+    let mut value;
+
+    let mut pieces = [0; PIECE_KEYS_SIZE];
+    let mut i = 0;
+    while i < PIECE_KEYS_SIZE {
+        (state, value) = splitmix64(state);
+        pieces[i] = value;
+        i += 1;
+    }
+
+    let mut castle = [0; 4];
+    let mut i = 0;
+    while i < 4 {
+        (state, value) = splitmix64(state);
+        castle[i] = value;
+        i += 1;
+    }
+
+    let mut en_passant = [0; 8];
+    let mut i = 0;
+    while i < 8 {
+        (state, value) = splitmix64(state);
+        en_passant[i] = value;
+        i += 1;
+    }
+
+    (_, value) = splitmix64(state);
+
+    ZobristKeys {
+        pieces,
+        color: value,
+        castle,
+        en_passant,
+    }
+}
+
+pub fn piece_key(piece: &Piece) -> u64 {
+    let color_index = match piece.color {
+        Color::White => 0,
+        Color::Black => 1,
+        Color::Yellow => 2
+    };
+
+    let kind_index = match piece.kind {
+        PieceKind::Pawn => 0,
+        PieceKind::Rook => 1,
+        PieceKind::Knight => 2,
+        PieceKind::Bishop => 3,
+        PieceKind::Queen => 4,
+        PieceKind::King => 5,
+        PieceKind::Duck => 6,
+    };
+
+    let pos_index = piece.pos.0 + 8 * piece.pos.1;
+    let index = pos_index + 64 * (color_index + 3 * kind_index);
+    KEYS.pieces[index as usize]
+}
+
+pub fn color_key() -> u64 {
+    KEYS.color
+}
+
+pub fn castle_key(white: Castle, black: Castle) -> u64 {
+    let rights = [white.short, white.long, black.short, black.long];
+    let mut key = 0;
+
+    for (i, right) in rights.into_iter().enumerate() {
+        if right {
+            key ^= KEYS.castle[i];
+        }
+    }
+
+    key
+}
+
+pub fn en_passant_key(en_passant: Option<Position>) -> u64 {
+    match en_passant {
+        Some(pos) => KEYS.en_passant[pos.0 as usize],
+        None => 0,
+    }
+}
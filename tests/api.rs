@@ -0,0 +1,134 @@
+use deep_duck::movements::perft;
+use deep_duck::{count_centipawns, evaluate, evaluate_limited, search, Board, Color, FenError, GameResult};
+use deep_duck::{Movement, PieceKind, Position, SanError, SearchLimits, SearchSettings, ZobristCache};
+use std::time::Duration;
+
+#[test]
+fn fen_round_trip() {
+    let fen = "r3k2r/8/3*4/3pP3/8/8/8/R3K2R w KQkq d6 0 12";
+    let board = Board::from_fen(fen).unwrap();
+
+    assert_eq!(board.to_fen(), fen);
+    assert_eq!(board.duck(), Some(Position(3, 5)));
+    assert_eq!(board.active_color(), Color::White);
+    assert_eq!(board.en_passant(), Some(Position(3, 5)));
+    assert_eq!((board.halfmove_clock(), board.fullmove_number()), (0, 12));
+    assert!(board.castle(Color::Black).short && board.castle(Color::Black).long);
+
+    assert!(matches!(Board::from_fen("8/8/8/8/8/8/8/8 w"), Err(FenError::MissingKing(_))));
+    assert!(Board::from_fen("").is_err());
+}
+
+#[test]
+fn movement_generation() {
+    let mut board = Board::arranged();
+    assert_eq!(Movement::avaliable_moves(&board).len(), 20);
+    assert_eq!(perft(&mut board, 2), 400);
+
+    // the 32 empty squares, minus the one the knight lands on, plus the one it leaves
+    let movement = Movement::from_str(&board, "g1f3,g1").unwrap();
+    assert_eq!(Movement::duck_moves(&board, &movement).len(), 32);
+}
+
+#[test]
+fn play_and_take_back() {
+    let mut board = Board::arranged();
+    let start = board.to_fen();
+
+    let movements = ["e2e4,d5", "d7d6,e6", "e4e5,d4"];
+    let mut played = Vec::new();
+
+    for text in movements {
+        let movement = Movement::from_str(&board, text).unwrap();
+        assert_eq!(movement.to_string(), text);
+        board.make_movement(movement);
+        played.push(movement);
+    }
+
+    assert_eq!(board.active_color(), Color::Black);
+    assert_eq!(board.get_square(Position(4, 4)).unwrap().kind, PieceKind::Pawn);
+
+    // the duck must leave its square
+    assert!(Movement::from_str(&board, "d6e5,d4").is_none());
+    assert!(Movement::try_movement(&board, Position(3, 5), Position(4, 4), Position(3, 3), None).is_none());
+    assert!(Movement::try_movement(&board, Position(3, 5), Position(4, 4), Position(3, 4), None).is_some());
+
+    for movement in played.into_iter().rev() {
+        board.unmake_movement(movement);
+    }
+    assert_eq!(board.to_fen(), start);
+}
+
+#[test]
+fn game_results() {
+    let mut board = Board::from_fen("4k3/8/8/8/8/8/8/4K2R w - - 0 1").unwrap();
+    assert_eq!(board.outcome(), None);
+
+    let movement = Movement::from_str(&board, "h1h8,a1").unwrap();
+    board.make_movement(movement);
+    let movement = Movement::from_str(&board, "e8f8,e8").unwrap();
+    board.make_movement(movement);
+    let capture = Movement::from_str(&board, "h8f8,a1").unwrap();
+    board.make_movement(capture);
+
+    let result = board.outcome().unwrap();
+    assert_eq!(result, GameResult::KingCaptured(Color::White));
+    assert_eq!(result.score(), "1-0");
+}
+
+#[test]
+fn search_captures_the_king() {
+    // the black king can not run away from the rook and the white king
+    let board = Board::from_fen("k7/8/1K6/8/8/8/8/7R w - - 0 1").unwrap();
+    let evaluation = evaluate(&board, 3);
+
+    assert!(evaluation.score > 0);
+    assert!(evaluation.movement.is_some());
+    assert_eq!(evaluation.pv.first().map(|x| x.to_string()), evaluation.movement.map(|x| x.to_string()));
+    assert!(search(&board, 3).is_some());
+}
+
+#[test]
+fn search_with_limits() {
+    let board = Board::arranged();
+    let cache = ZobristCache::with_size(1);
+    let settings = SearchSettings::default();
+
+    let evaluation = evaluate_limited(&board, SearchLimits::depth(3), &cache, &settings);
+    assert_eq!(evaluation.depth, 3);
+    assert!(evaluation.stats.total_nodes() > 0);
+
+    let limits = SearchLimits {
+        time: Some(Duration::from_millis(50)),
+        ..SearchLimits::default()
+    };
+    let evaluation = evaluate_limited(&board, limits, &cache, &settings);
+    assert!(evaluation.movement.is_some());
+    assert!(evaluation.stats.elapsed < Duration::from_secs(1));
+}
+
+#[test]
+fn static_evaluation() {
+    assert_eq!(count_centipawns(&Board::arranged()), 0);
+
+    // a queen up for white is good for white and bad for black
+    let white = Board::from_fen("4k3/8/8/8/8/8/8/3QK3 w - - 0 1").unwrap();
+    let black = Board::from_fen("4k3/8/8/8/8/8/8/3QK3 b - - 0 1").unwrap();
+    assert!(count_centipawns(&white) > 0);
+    assert_eq!(count_centipawns(&white), -count_centipawns(&black));
+}
+
+#[test]
+fn algebraic_notation() {
+    let mut board = Board::arranged();
+
+    for san in ["e4@d5", "Nc6,d4", "Nf3@e6", "Nd4@c3"] {
+        let movement = Movement::from_san(&board, san).unwrap();
+        assert_eq!(movement.to_san(&board), san.replace(',', "@"));
+        board.make_movement(movement);
+    }
+
+    let capture = Movement::from_san(&board, "Nxd4@e5").unwrap();
+    assert_eq!(capture.captured.map(|x| x.kind), Some(PieceKind::Knight));
+    assert_eq!(Movement::from_san(&board, "Nxd4@c3").err(), Some(SanError::DuckNotFree(Position(2, 2))));
+}